@@ -0,0 +1,364 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use revm::primitives::{AccountInfo, Address, HashMap, B256, U256};
+#[cfg(feature = "persistence")]
+use serde::{Deserialize, Serialize};
+
+use crate::NewFetch;
+
+/// Derives an EIP-2930 style access list from recorded fetches.
+///
+/// Every touched account is listed, with its storage slots deduplicated. Accounts
+/// and slots are sorted so the output does not depend on the recording order.
+pub fn access_list_from_fetches(
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> Vec<(Address, Vec<U256>)> {
+    let mut access_list = BTreeMap::<Address, BTreeSet<U256>>::new();
+    for fetch in fetches {
+        match fetch {
+            NewFetch::Basic { address, .. } | NewFetch::BasicMissing { address } => {
+                access_list.entry(address).or_default();
+            }
+            NewFetch::Storage { address, index, .. } => {
+                access_list.entry(address).or_default().insert(index);
+            }
+            NewFetch::CodeByHash { .. }
+            | NewFetch::BlockHash { .. }
+            | NewFetch::Error { .. }
+            | NewFetch::CacheHit { .. }
+            | NewFetch::BasicWrite { .. }
+            | NewFetch::StorageWrite { .. }
+            | NewFetch::Summary { .. } => {}
+        }
+    }
+    access_list
+        .into_iter()
+        .map(|(address, slots)| (address, slots.into_iter().collect()))
+        .collect()
+}
+
+/// Maps the hash of every contract a recording read to its bytecode length, in bytes.
+///
+/// Code inlined in a `Basic` fetch counts as well as `CodeByHash` fetches. Each hash is
+/// listed once, however many times its code was read; empty code is left out.
+pub fn code_size_histogram(fetches: impl IntoIterator<Item = NewFetch>) -> BTreeMap<B256, usize> {
+    let mut sizes = BTreeMap::new();
+    for fetch in fetches {
+        let (code_hash, bytecode) = match fetch {
+            NewFetch::CodeByHash {
+                code_hash,
+                bytecode,
+            } => (code_hash, bytecode),
+            NewFetch::Basic { account_info, .. } => match account_info.code {
+                Some(bytecode) => (account_info.code_hash, bytecode),
+                None => continue,
+            },
+            NewFetch::BasicMissing { .. }
+            | NewFetch::Storage { .. }
+            | NewFetch::BlockHash { .. }
+            | NewFetch::Error { .. }
+            | NewFetch::CacheHit { .. }
+            | NewFetch::BasicWrite { .. }
+            | NewFetch::StorageWrite { .. }
+            | NewFetch::Summary { .. } => continue,
+        };
+        if !bytecode.is_empty() {
+            sizes.insert(code_hash, bytecode.len());
+        }
+    }
+    sizes
+}
+
+/// What a recording read from one account, see [`group_fetches_by_account`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct AccountAccess {
+    /// `None` if the account was only read from storage, or read as missing.
+    pub info: Option<AccountInfo>,
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// Collapses recorded fetches into one entry per touched account. A key recorded
+/// several times keeps its last value.
+pub fn group_fetches_by_account(
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> HashMap<Address, AccountAccess> {
+    let mut accounts = HashMap::<Address, AccountAccess>::default();
+    for fetch in fetches {
+        match fetch {
+            NewFetch::Basic {
+                address,
+                account_info,
+            } => accounts.entry(address).or_default().info = Some(account_info),
+            NewFetch::BasicMissing { address } => {
+                accounts.entry(address).or_default();
+            }
+            NewFetch::Storage {
+                address,
+                index,
+                value,
+            } => {
+                accounts
+                    .entry(address)
+                    .or_default()
+                    .storage
+                    .insert(index, value);
+            }
+            NewFetch::CodeByHash { .. }
+            | NewFetch::BlockHash { .. }
+            | NewFetch::Error { .. }
+            | NewFetch::CacheHit { .. }
+            | NewFetch::BasicWrite { .. }
+            | NewFetch::StorageWrite { .. }
+            | NewFetch::Summary { .. } => {}
+        }
+    }
+    accounts
+}
+
+/// Everything a recording read, keyed and sorted so two reports can be compared.
+///
+/// Like [`group_fetches_by_account`], a key recorded several times keeps its last value.
+/// Failed lookups, cache hits and summaries are not part of the report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct AccessReport {
+    accounts: BTreeMap<Address, AccountAccess>,
+    code_hashes: BTreeSet<B256>,
+    block_hashes: BTreeMap<u64, B256>,
+    total_reads: u64,
+}
+
+impl AccessReport {
+    pub fn from_fetches(fetches: impl IntoIterator<Item = NewFetch>) -> Self {
+        let mut report = Self::default();
+        for fetch in fetches {
+            match fetch {
+                NewFetch::Basic {
+                    address,
+                    account_info,
+                } => report.account(address).info = Some(account_info),
+                NewFetch::BasicMissing { address } => {
+                    report.account(address);
+                }
+                NewFetch::Storage {
+                    address,
+                    index,
+                    value,
+                } => {
+                    report.account(address).storage.insert(index, value);
+                }
+                NewFetch::CodeByHash { code_hash, .. } => {
+                    report.code_hashes.insert(code_hash);
+                }
+                NewFetch::BlockHash { number, hash } => {
+                    report.block_hashes.insert(number, hash);
+                }
+                NewFetch::Error { .. }
+                | NewFetch::CacheHit { .. }
+                | NewFetch::BasicWrite { .. }
+                | NewFetch::StorageWrite { .. }
+                | NewFetch::Summary { .. } => continue,
+            }
+            report.total_reads += 1;
+        }
+        report
+    }
+
+    fn account(&mut self, address: Address) -> &mut AccountAccess {
+        self.accounts.entry(address).or_default()
+    }
+
+    /// Every touched account, by address.
+    pub fn accounts(&self) -> &BTreeMap<Address, AccountAccess> {
+        &self.accounts
+    }
+
+    /// The slots read from `address`, `None` if the account was not touched at all.
+    pub fn storage_of(&self, address: Address) -> Option<&BTreeMap<U256, U256>> {
+        self.accounts.get(&address).map(|account| &account.storage)
+    }
+
+    /// Hashes of the code read by hash. Code inlined in account info is not listed.
+    pub fn code_hashes(&self) -> &BTreeSet<B256> {
+        &self.code_hashes
+    }
+
+    pub fn block_hashes(&self) -> &BTreeMap<u64, B256> {
+        &self.block_hashes
+    }
+
+    /// Number of values recorded, counting keys read several times once per read.
+    pub fn total_reads(&self) -> u64 {
+        self.total_reads
+    }
+
+    /// The report as an access list, see [`access_list_from_fetches`].
+    pub fn to_access_list(&self) -> Vec<(Address, Vec<U256>)> {
+        self.accounts
+            .iter()
+            .map(|(address, account)| (*address, account.storage.keys().copied().collect()))
+            .collect()
+    }
+}
+
+impl FromIterator<NewFetch> for AccessReport {
+    fn from_iter<T: IntoIterator<Item = NewFetch>>(fetches: T) -> Self {
+        Self::from_fetches(fetches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::primitives::{Bytecode, Bytes};
+
+    use super::*;
+    use crate::{FetchKey, FetchKind};
+
+    fn storage(address: Address, index: u64) -> NewFetch {
+        NewFetch::Storage {
+            address,
+            index: U256::from(index),
+            value: U256::from(1),
+        }
+    }
+
+    #[test]
+    fn access_list_is_sorted_and_deduplicated() {
+        let [first, second, missing] = [1, 2, 3].map(Address::with_last_byte);
+        let fetches = vec![
+            storage(second, 7),
+            storage(first, 2),
+            NewFetch::BasicMissing { address: missing },
+            storage(second, 3),
+            storage(second, 7),
+            NewFetch::BlockHash {
+                number: 1,
+                hash: B256::ZERO,
+            },
+            NewFetch::Basic {
+                address: first,
+                account_info: AccountInfo::default(),
+            },
+        ];
+
+        assert_eq!(
+            access_list_from_fetches(fetches),
+            [
+                (first, vec![U256::from(2)]),
+                (second, vec![U256::from(3), U256::from(7)]),
+                (missing, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn access_report_keeps_the_last_value_of_every_read() {
+        let [holder, reader, missing] = [1, 2, 3].map(Address::with_last_byte);
+        let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+        let code_hash = bytecode.hash_slow();
+        let info = AccountInfo {
+            nonce: 1,
+            ..Default::default()
+        };
+        let report: AccessReport = vec![
+            storage(holder, 2),
+            NewFetch::Storage {
+                address: holder,
+                index: U256::from(2),
+                value: U256::from(5),
+            },
+            NewFetch::Basic {
+                address: holder,
+                account_info: info.clone(),
+            },
+            storage(reader, 1),
+            NewFetch::BasicMissing { address: missing },
+            NewFetch::CodeByHash {
+                code_hash,
+                bytecode,
+            },
+            NewFetch::BlockHash {
+                number: 9,
+                hash: B256::repeat_byte(9),
+            },
+            NewFetch::CacheHit {
+                key: FetchKey::Basic(holder),
+            },
+            NewFetch::Error {
+                kind: FetchKind::Basic,
+                address: Some(reader),
+                index: None,
+                message: "unavailable".into(),
+            },
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            report.accounts().keys().copied().collect::<Vec<_>>(),
+            [holder, reader, missing]
+        );
+        assert_eq!(report.accounts()[&holder].info, Some(info));
+        assert_eq!(report.accounts()[&reader].info, None);
+        assert_eq!(
+            report.storage_of(holder),
+            Some(&BTreeMap::from([(U256::from(2), U256::from(5))]))
+        );
+        assert_eq!(report.storage_of(missing), Some(&BTreeMap::new()));
+        assert_eq!(report.storage_of(Address::ZERO), None);
+        assert_eq!(report.code_hashes(), &BTreeSet::from([code_hash]));
+        assert_eq!(
+            report.block_hashes(),
+            &BTreeMap::from([(9, B256::repeat_byte(9))])
+        );
+        assert_eq!(report.total_reads(), 7);
+        assert_eq!(
+            report.to_access_list(),
+            [
+                (holder, vec![U256::from(2)]),
+                (reader, vec![U256::from(1)]),
+                (missing, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_size_histogram_lists_every_read_code_once() {
+        let small = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+        let large = Bytecode::new_raw(Bytes::from(vec![0x5b; 100]));
+        let empty = Bytecode::new();
+        let fetches = vec![
+            NewFetch::CodeByHash {
+                code_hash: small.hash_slow(),
+                bytecode: small.clone(),
+            },
+            NewFetch::Basic {
+                address: Address::with_last_byte(1),
+                account_info: AccountInfo::from_bytecode(large.clone()),
+            },
+            NewFetch::CodeByHash {
+                code_hash: small.hash_slow(),
+                bytecode: small.clone(),
+            },
+            NewFetch::CodeByHash {
+                code_hash: empty.hash_slow(),
+                bytecode: empty,
+            },
+            // Account info without inline code says nothing about its size.
+            NewFetch::Basic {
+                address: Address::with_last_byte(2),
+                account_info: AccountInfo {
+                    code: None,
+                    ..AccountInfo::from_bytecode(Bytecode::new_raw(Bytes::from_static(&[0])))
+                },
+            },
+            storage(Address::with_last_byte(1), 0),
+        ];
+
+        assert_eq!(
+            code_size_histogram(fetches),
+            BTreeMap::from([(small.hash_slow(), 3), (large.hash_slow(), 100)])
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::{fmt, future::Future};
+
+use revm::primitives::{AccountInfo, Address, Bytecode, B256, U256};
+
+use crate::{latency, FetchKey, FetchKind, FetchSink, RecordingDb};
+
+/// Async counterpart of [`DatabaseRef`](revm::DatabaseRef), for backends such as RPC
+/// providers that would otherwise need a `block_on` bridge.
+pub trait AsyncDatabaseRef {
+    type Error;
+
+    fn basic_async_ref(
+        &self,
+        address: Address,
+    ) -> impl Future<Output = Result<Option<AccountInfo>, Self::Error>> + Send;
+
+    fn code_by_hash_async_ref(
+        &self,
+        code_hash: B256,
+    ) -> impl Future<Output = Result<Bytecode, Self::Error>> + Send;
+
+    fn storage_async_ref(
+        &self,
+        address: Address,
+        index: U256,
+    ) -> impl Future<Output = Result<U256, Self::Error>> + Send;
+
+    fn block_hash_async_ref(
+        &self,
+        number: u64,
+    ) -> impl Future<Output = Result<B256, Self::Error>> + Send;
+}
+
+impl<ExtDB, S> AsyncDatabaseRef for RecordingDb<ExtDB, S>
+where
+    ExtDB: AsyncDatabaseRef + Sync,
+    ExtDB::Error: fmt::Display,
+    S: FetchSink + Sync,
+{
+    type Error = ExtDB::Error;
+
+    async fn basic_async_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        if let Some(account_info) = self.cached(FetchKey::Basic(address), |c| c.basic(address)) {
+            return Ok(account_info);
+        }
+        let account_info =
+            latency::time_async(self.latency.as_deref(), self.db.basic_async_ref(address))
+                .await
+                .inspect_err(|err| self.record_error(FetchKind::Basic, Some(address), None, err))?;
+        self.record_basic(address, &account_info);
+        Ok(account_info)
+    }
+
+    async fn code_by_hash_async_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if let Some(bytecode) = self.cached(FetchKey::CodeByHash(code_hash), |c| {
+            c.code_by_hash(code_hash)
+        }) {
+            return Ok(bytecode);
+        }
+        let bytecode = latency::time_async(
+            self.latency.as_deref(),
+            self.db.code_by_hash_async_ref(code_hash),
+        )
+        .await
+        .inspect_err(|err| self.record_error(FetchKind::CodeByHash, None, None, err))?;
+        self.record_code_by_hash(code_hash, &bytecode);
+        Ok(bytecode)
+    }
+
+    async fn storage_async_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        if let Some(value) = self.cached(FetchKey::Storage(address, index), |c| {
+            c.storage(address, index)
+        }) {
+            return Ok(value);
+        }
+        let value = latency::time_async(
+            self.latency.as_deref(),
+            self.db.storage_async_ref(address, index),
+        )
+        .await
+        .inspect_err(|err| {
+            self.record_error(FetchKind::Storage, Some(address), Some(index), err)
+        })?;
+        self.record_storage(address, index, value);
+        Ok(value)
+    }
+
+    async fn block_hash_async_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        if let Some(hash) = self.cached(FetchKey::BlockHash(number), |c| c.block_hash(number)) {
+            return Ok(hash);
+        }
+        let hash = latency::time_async(
+            self.latency.as_deref(),
+            self.db.block_hash_async_ref(number),
+        )
+        .await
+        .inspect_err(|err| self.record_error(FetchKind::BlockHash, None, None, err))?;
+        self.record_block_hash(number, hash);
+        Ok(hash)
+    }
+}
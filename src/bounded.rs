@@ -0,0 +1,148 @@
+use std::{fmt, hash::Hash, num::NonZeroUsize};
+
+use lru::LruCache;
+use revm::primitives::{HashMap, HashSet};
+
+/// A set that optionally forgets its least recently seen keys beyond a capacity.
+pub(crate) enum KeySet<K> {
+    Unbounded(HashSet<K>),
+    Lru(LruCache<K, ()>),
+}
+
+impl<K: Eq + Hash> KeySet<K> {
+    /// A capacity of zero is treated as one.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self::Lru(LruCache::new(
+            NonZeroUsize::MIN.saturating_add(capacity.saturating_sub(1)),
+        ))
+    }
+
+    /// Returns `true` if `key` was not in the set, marking it as recently seen either way.
+    pub(crate) fn insert(&mut self, key: K) -> bool {
+        match self {
+            Self::Unbounded(set) => set.insert(key),
+            Self::Lru(lru) => lru.put(key, ()).is_none(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            Self::Unbounded(set) => set.clear(),
+            Self::Lru(lru) => lru.clear(),
+        }
+    }
+}
+
+impl<K: Eq + Hash> fmt::Debug for KeySet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unbounded(set) => f
+                .debug_struct("Unbounded")
+                .field("len", &set.len())
+                .finish(),
+            Self::Lru(lru) => f
+                .debug_struct("Lru")
+                .field("len", &lru.len())
+                .field("cap", &lru.cap())
+                .finish(),
+        }
+    }
+}
+
+impl<K> Default for KeySet<K> {
+    fn default() -> Self {
+        Self::Unbounded(HashSet::default())
+    }
+}
+
+/// A map that optionally evicts its least recently used entries beyond a capacity.
+pub(crate) enum KeyMap<K, V> {
+    Unbounded(HashMap<K, V>),
+    Lru(LruCache<K, V>),
+}
+
+impl<K: Eq + Hash, V> KeyMap<K, V> {
+    /// A capacity of zero is treated as one.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self::Lru(LruCache::new(
+            NonZeroUsize::MIN.saturating_add(capacity.saturating_sub(1)),
+        ))
+    }
+
+    pub(crate) fn get(&mut self, key: &K) -> Option<&V> {
+        match self {
+            Self::Unbounded(map) => map.get(key),
+            Self::Lru(lru) => lru.get(key),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        match self {
+            Self::Unbounded(map) => {
+                map.insert(key, value);
+            }
+            Self::Lru(lru) => {
+                lru.put(key, value);
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &K) {
+        match self {
+            Self::Unbounded(map) => {
+                map.remove(key);
+            }
+            Self::Lru(lru) => {
+                lru.pop(key);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            Self::Unbounded(map) => map.clear(),
+            Self::Lru(lru) => lru.clear(),
+        }
+    }
+
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&K) -> bool)
+    where
+        K: Clone,
+    {
+        match self {
+            Self::Unbounded(map) => map.retain(|key, _| keep(key)),
+            Self::Lru(lru) => {
+                let dropped: Vec<K> = lru
+                    .iter()
+                    .filter(|(key, _)| !keep(key))
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                for key in dropped {
+                    lru.pop(&key);
+                }
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> fmt::Debug for KeyMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unbounded(map) => f
+                .debug_struct("Unbounded")
+                .field("len", &map.len())
+                .finish(),
+            Self::Lru(lru) => f
+                .debug_struct("Lru")
+                .field("len", &lru.len())
+                .field("cap", &lru.cap())
+                .finish(),
+        }
+    }
+}
+
+impl<K, V> Default for KeyMap<K, V> {
+    fn default() -> Self {
+        Self::Unbounded(HashMap::default())
+    }
+}
@@ -0,0 +1,42 @@
+use revm::db::{
+    states::{bundle_state::BundleState, AccountStatus, BundleAccount, StorageSlot},
+    AccountState,
+};
+
+use crate::{cache_db_from_fetches, NewFetch, ProxyDbError};
+
+/// Builds a [`BundleState`] holding the recorded `fetches` as unchanged loaded state, to
+/// seed a revm `State` with `with_bundle_prestate`.
+///
+/// Fetches are first reconciled as by [`apply_fetches_to_cache_db`], so their order does
+/// not matter. Accounts fetched as missing become not existing. Block hashes and failed
+/// lookups have no bundle representation and are dropped.
+///
+/// [`apply_fetches_to_cache_db`]: crate::apply_fetches_to_cache_db
+pub fn fetches_to_bundle_state(
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> Result<BundleState, ProxyDbError> {
+    let cache_db = cache_db_from_fetches(fetches)?;
+    let mut bundle = BundleState::default();
+    for (address, account) in cache_db.accounts {
+        let storage = account
+            .storage
+            .into_iter()
+            .map(|(index, value)| (index, StorageSlot::new(value)))
+            .collect();
+        let bundle_account = if account.account_state == AccountState::NotExisting {
+            BundleAccount::new(None, None, storage, AccountStatus::LoadedNotExisting)
+        } else {
+            let info = Some(account.info);
+            BundleAccount::new(info.clone(), info, storage, AccountStatus::Loaded)
+        };
+        bundle.state_size += bundle_account.size_hint();
+        bundle.state.insert(address, bundle_account);
+    }
+    bundle.contracts = cache_db
+        .contracts
+        .into_iter()
+        .filter(|(_, bytecode)| !bytecode.is_empty())
+        .collect();
+    Ok(bundle)
+}
@@ -0,0 +1,754 @@
+use std::fmt::Write;
+
+use revm::{
+    db::{AccountState, CacheDB, DbAccount, EmptyDB},
+    primitives::{
+        alloy_primitives::Keccak256, AccountInfo, Address, Bytecode, HashMap, Log, B256,
+        KECCAK_EMPTY, U256,
+    },
+};
+
+use crate::ProxyDbError;
+
+/// Merges `other` into `base`.
+///
+/// Accounts, contracts and block hashes are unioned and per-account storage maps are
+/// merged. Whenever both caches hold a value for the same key (account info, storage
+/// slot, code hash or block number), the one from `other` wins. An account `other` holds
+/// as cleared or missing replaces the storage `base` holds for it instead.
+pub fn merge_cache_dbs<ExtDB, OtherDB>(base: &mut CacheDB<ExtDB>, other: &CacheDB<OtherDB>) {
+    for (address, account) in &other.accounts {
+        merge_account(
+            base.accounts.entry(*address).or_default(),
+            account.info.clone(),
+            account,
+        );
+    }
+    base.contracts.extend(
+        other
+            .contracts
+            .iter()
+            .map(|(code_hash, bytecode)| (*code_hash, bytecode.clone())),
+    );
+    base.block_hashes.extend(
+        other
+            .block_hashes
+            .iter()
+            .map(|(number, hash)| (*number, *hash)),
+    );
+}
+
+/// Whether an account in this state hides every slot held for it by a cache merged
+/// below it, i.e. whether it was cleared or found missing.
+fn replaces_storage(account_state: &AccountState) -> bool {
+    matches!(
+        account_state,
+        AccountState::StorageCleared | AccountState::NotExisting
+    )
+}
+
+fn merge_account(base_account: &mut DbAccount, info: AccountInfo, account: &DbAccount) {
+    if replaces_storage(&account.account_state) {
+        base_account.storage.clear();
+    }
+    base_account.info = info;
+    base_account.account_state = account.account_state.clone();
+    base_account.storage.extend(
+        account
+            .storage
+            .iter()
+            .map(|(index, value)| (*index, *value)),
+    );
+}
+
+/// Like [`merge_cache_dbs`], but interns contracts, for merging many overlapping caches:
+/// bytecode `base` already holds under a code hash is kept, and shared with the merged
+/// accounts using it, instead of being replaced by another copy.
+///
+/// A code hash held by both caches, in the contracts map or inline in `other`'s
+/// accounts, with different bytecode fails with [`ProxyDbError::CorruptCache`] for
+/// whichever entry does not match it, before anything is merged. Differently analyzed
+/// forms of the same bytecode still match.
+pub fn merge_cache_dbs_interned<ExtDB, OtherDB>(
+    base: &mut CacheDB<ExtDB>,
+    other: &CacheDB<OtherDB>,
+) -> Result<(), ProxyDbError> {
+    let inline_code = other.accounts.values().filter_map(|account| {
+        let code = account.info.code.as_ref()?;
+        Some((&account.info.code_hash, code))
+    });
+    for (code_hash, bytecode) in other.contracts.iter().chain(inline_code) {
+        if let Some(interned) = base.contracts.get(code_hash) {
+            if interned.original_bytes() != bytecode.original_bytes() {
+                check_code_hash(*code_hash, bytecode)?;
+                check_code_hash(*code_hash, interned)?;
+            }
+        }
+    }
+    for (code_hash, bytecode) in &other.contracts {
+        base.contracts
+            .entry(*code_hash)
+            .or_insert_with(|| bytecode.clone());
+    }
+    for (address, account) in &other.accounts {
+        let mut info = account.info.clone();
+        if let (Some(code), Some(interned)) = (&mut info.code, base.contracts.get(&info.code_hash))
+        {
+            *code = interned.clone();
+        }
+        merge_account(base.accounts.entry(*address).or_default(), info, account);
+    }
+    base.block_hashes.extend(
+        other
+            .block_hashes
+            .iter()
+            .map(|(number, hash)| (*number, *hash)),
+    );
+    Ok(())
+}
+
+/// A db whose cached content [`flatten_cache_db`] can collapse into a single cache.
+///
+/// Implemented for `CacheDB`s over other such dbs, down to an [`EmptyDB`], and for the
+/// recording and replay wrappers of this crate.
+pub trait CacheLayers {
+    /// Merges the cached content of every layer into `out`, innermost first, so outer
+    /// layers win.
+    fn flatten_into(&self, out: &mut CacheDB<EmptyDB>);
+}
+
+impl CacheLayers for EmptyDB {
+    fn flatten_into(&self, _out: &mut CacheDB<EmptyDB>) {}
+}
+
+impl<ExtDB: CacheLayers> CacheLayers for CacheDB<ExtDB> {
+    fn flatten_into(&self, out: &mut CacheDB<EmptyDB>) {
+        self.db.flatten_into(out);
+        merge_cache_dbs(out, self);
+        out.logs.extend(self.logs.iter().cloned());
+    }
+}
+
+impl<ExtDB: CacheLayers, S> CacheLayers for crate::RecordingDb<ExtDB, S> {
+    fn flatten_into(&self, out: &mut CacheDB<EmptyDB>) {
+        self.db.flatten_into(out)
+    }
+}
+
+impl CacheLayers for crate::ReplayDb {
+    fn flatten_into(&self, out: &mut CacheDB<EmptyDB>) {
+        self.cache().flatten_into(out)
+    }
+}
+
+/// Collapses `cache_db` and every cache layered below it into one detached cache, so
+/// nothing held by an inner `CacheDB` is lost when saving.
+pub fn flatten_cache_db<ExtDB: CacheLayers>(cache_db: &CacheDB<ExtDB>) -> CacheDB<EmptyDB> {
+    let mut out = CacheDB::new(EmptyDB::new());
+    cache_db.flatten_into(&mut out);
+    out
+}
+
+/// Fills the contracts map from bytecode embedded in the accounts of `cache_db`, so
+/// that hash-based code lookups succeed on imported dumps.
+///
+/// The code hash is recomputed from the bytecode and written back to the account,
+/// replacing a missing or stale value.
+pub fn rebuild_contracts_map<ExtDB>(cache_db: &mut CacheDB<ExtDB>) {
+    for account in cache_db.accounts.values_mut() {
+        let Some(code) = &account.info.code else {
+            continue;
+        };
+        if code.is_empty() {
+            continue;
+        }
+        let code_hash = code.hash_slow();
+        account.info.code_hash = code_hash;
+        cache_db
+            .contracts
+            .entry(code_hash)
+            .or_insert_with(|| code.clone());
+    }
+}
+
+/// Checks that every bytecode in the contracts map of `cache_db` hashes to its key.
+///
+/// The empty bytecode `CacheDB` stores under both [`KECCAK_EMPTY`] and the zero hash
+/// is accepted as is.
+pub fn verify_cache_db<ExtDB>(cache_db: &CacheDB<ExtDB>) -> Result<(), ProxyDbError> {
+    for (code_hash, bytecode) in &cache_db.contracts {
+        check_code_hash(*code_hash, bytecode)?;
+    }
+    Ok(())
+}
+
+/// Checks that `bytecode` hashes to `code_hash`, accepting empty bytecode under
+/// [`KECCAK_EMPTY`] or the zero hash.
+pub(crate) fn check_code_hash(code_hash: B256, bytecode: &Bytecode) -> Result<(), ProxyDbError> {
+    if bytecode.is_empty() && (code_hash == KECCAK_EMPTY || code_hash.is_zero()) {
+        return Ok(());
+    }
+    let actual = bytecode.hash_slow();
+    if actual != code_hash {
+        return Err(ProxyDbError::CorruptCache { code_hash, actual });
+    }
+    Ok(())
+}
+
+/// Returns the addresses of every account held by `cache_db`, sorted.
+///
+/// This includes accounts cached as not existing.
+pub fn cache_db_addresses<ExtDB>(cache_db: &CacheDB<ExtDB>) -> Vec<Address> {
+    let mut addresses: Vec<Address> = cache_db.accounts.keys().copied().collect();
+    addresses.sort_unstable();
+    addresses
+}
+
+/// Whether `cache_db` holds an account entry for `address`.
+pub fn cache_db_contains<ExtDB>(cache_db: &CacheDB<ExtDB>, address: Address) -> bool {
+    cache_db.accounts.contains_key(&address)
+}
+
+/// Roughly estimates the size of `cache_db` in bytes, without serializing it.
+///
+/// Counts the raw width of the cached values: 92 bytes per account (address, balance,
+/// nonce and code hash) plus any inline code, 64 bytes per storage slot and block hash,
+/// and 32 bytes plus the code length per contract. Logs and map overhead are ignored,
+/// so the result is a lower bound on memory use and within a small factor of the
+/// binary encoding.
+pub fn estimate_cache_db_size<ExtDB>(cache_db: &CacheDB<ExtDB>) -> usize {
+    let accounts: usize = cache_db
+        .accounts
+        .values()
+        .map(|account| {
+            92 + account.info.code.as_ref().map_or(0, |code| code.len())
+                + 64 * account.storage.len()
+        })
+        .sum();
+    let contracts: usize = cache_db
+        .contracts
+        .values()
+        .map(|code| 32 + code.len())
+        .sum();
+    accounts + contracts + 64 * cache_db.block_hashes.len()
+}
+
+/// Copies the accounts of `cache_db` listed in `addresses`, with their storage and the
+/// contracts they reference by code hash, into a detached cache.
+///
+/// Contracts only referenced by other accounts are dropped, block hashes are kept and
+/// logs are not copied. Listed addresses missing from `cache_db` are ignored.
+pub fn cache_db_subset<ExtDB>(
+    cache_db: &CacheDB<ExtDB>,
+    addresses: &[Address],
+) -> CacheDB<EmptyDB> {
+    let mut subset = CacheDB::new(EmptyDB::new());
+    for address in addresses {
+        let Some(account) = cache_db.accounts.get(address) else {
+            continue;
+        };
+        let code_hash = account.info.code_hash;
+        if let Some(bytecode) = cache_db.contracts.get(&code_hash) {
+            subset.contracts.insert(code_hash, bytecode.clone());
+        }
+        subset.accounts.insert(*address, account.clone());
+    }
+    subset.block_hashes.clone_from(&cache_db.block_hashes);
+    subset
+}
+
+/// Hashes the cached state of `cache_db` into a digest that only depends on its content,
+/// not on map iteration order or on how the cache was built.
+///
+/// Covers accounts with their state and storage, contracts and block hashes, each sorted
+/// by key. Inline account code is covered through its code hash, and empty contracts are
+/// skipped since every `CacheDB` holds one by default. Logs are ignored.
+pub fn cache_db_fingerprint<ExtDB>(cache_db: &CacheDB<ExtDB>) -> B256 {
+    let mut hasher = Keccak256::new();
+    let addresses = cache_db_addresses(cache_db);
+    hasher.update((addresses.len() as u64).to_be_bytes());
+    for address in addresses {
+        let account = &cache_db.accounts[&address];
+        hasher.update(address);
+        hasher.update([match account.account_state {
+            AccountState::NotExisting => 0,
+            AccountState::Touched => 1,
+            AccountState::StorageCleared => 2,
+            AccountState::None => 3,
+        }]);
+        hasher.update(account.info.balance.to_be_bytes::<32>());
+        hasher.update(account.info.nonce.to_be_bytes());
+        hasher.update(account.info.code_hash);
+        let mut storage: Vec<_> = account.storage.iter().collect();
+        storage.sort_unstable();
+        hasher.update((storage.len() as u64).to_be_bytes());
+        for (index, value) in storage {
+            hasher.update(index.to_be_bytes::<32>());
+            hasher.update(value.to_be_bytes::<32>());
+        }
+    }
+    let mut contracts: Vec<_> = cache_db
+        .contracts
+        .iter()
+        .filter(|(_, bytecode)| !bytecode.is_empty())
+        .collect();
+    contracts.sort_unstable_by_key(|(code_hash, _)| **code_hash);
+    hasher.update((contracts.len() as u64).to_be_bytes());
+    for (code_hash, bytecode) in contracts {
+        let bytes = bytecode.original_byte_slice();
+        hasher.update(code_hash);
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    }
+    let mut block_hashes: Vec<_> = cache_db.block_hashes.iter().collect();
+    block_hashes.sort_unstable();
+    hasher.update((block_hashes.len() as u64).to_be_bytes());
+    for (number, hash) in block_hashes {
+        hasher.update(number.to_be_bytes::<32>());
+        hasher.update(hash);
+    }
+    hasher.finalize()
+}
+
+/// Renders the accounts of `cache_db` as a human-readable report, for debugging.
+///
+/// Each account is printed on one line with its balance, nonce, code hash and code
+/// length, followed by one indented `index => value` line per storage slot. Accounts and
+/// slots are sorted. The layout is not stable and not meant to be parsed.
+pub fn dump_cache_db<ExtDB>(cache_db: &CacheDB<ExtDB>) -> String {
+    let mut out = String::new();
+    for address in cache_db_addresses(cache_db) {
+        let account = &cache_db.accounts[&address];
+        let info = &account.info;
+        let code_len = info
+            .code
+            .as_ref()
+            .or_else(|| cache_db.contracts.get(&info.code_hash))
+            .map_or(0, |code| code.len());
+        let _ = writeln!(
+            out,
+            "{address} state={:?} balance={:#x} nonce={} code_hash={} code_len={code_len}",
+            account.account_state, info.balance, info.nonce, info.code_hash,
+        );
+        let mut storage: Vec<_> = account.storage.iter().collect();
+        storage.sort_unstable();
+        for (index, value) in storage {
+            let _ = writeln!(out, "    {index:#x} => {value:#x}");
+        }
+    }
+    out
+}
+
+/// Returns the part of `current` that is new or changed since `base`, as a cache that
+/// [`merge_cache_dbs`] turns `base` back into `current` with.
+///
+/// Changed accounts carry their full info but only their new or changed storage slots,
+/// except cleared or missing accounts, which carry all of them.
+/// Removals cannot be expressed: accounts, slots, contracts or block hashes missing from
+/// `current` are kept when the delta is merged. Logs are not included.
+pub fn cache_db_delta<ExtDB, OtherDB>(
+    base: &CacheDB<ExtDB>,
+    current: &CacheDB<OtherDB>,
+) -> CacheDB<EmptyDB> {
+    let mut delta = CacheDB::new(EmptyDB::new());
+    delta.contracts.clear();
+    for (address, account) in &current.accounts {
+        let base_account = base.accounts.get(address);
+        let mut storage: HashMap<U256, U256> = account
+            .storage
+            .iter()
+            .filter(|(index, value)| {
+                base_account.and_then(|base| base.storage.get(*index)) != Some(*value)
+            })
+            .map(|(index, value)| (*index, *value))
+            .collect();
+        let unchanged = base_account.is_some_and(|base| {
+            base.info == account.info && base.account_state == account.account_state
+        });
+        if unchanged && storage.is_empty() {
+            continue;
+        }
+        // Merging a cleared or missing account replaces its storage, so keep all of it.
+        if replaces_storage(&account.account_state) {
+            storage.clone_from(&account.storage);
+        }
+        delta.accounts.insert(
+            *address,
+            DbAccount {
+                info: account.info.clone(),
+                account_state: account.account_state.clone(),
+                storage,
+            },
+        );
+    }
+    delta.contracts.extend(
+        current
+            .contracts
+            .iter()
+            .filter(|(code_hash, _)| !base.contracts.contains_key(*code_hash))
+            .map(|(code_hash, bytecode)| (*code_hash, bytecode.clone())),
+    );
+    delta.block_hashes.extend(
+        current
+            .block_hashes
+            .iter()
+            .filter(|(number, hash)| base.block_hashes.get(*number) != Some(*hash))
+            .map(|(number, hash)| (*number, *hash)),
+    );
+    delta
+}
+
+/// The cached state of a `CacheDB` at one point in time, see [`snapshot_cache_db`].
+#[derive(Debug, Clone)]
+pub struct CacheSnapshot {
+    accounts: HashMap<Address, DbAccount>,
+    contracts: HashMap<B256, Bytecode>,
+    logs: Vec<Log>,
+    block_hashes: HashMap<U256, B256>,
+}
+
+/// Captures the cached state of `cache_db` so it can be rolled back to with
+/// [`restore_cache_db`].
+///
+/// This is a plain clone of the cached maps, so its cost grows with the size of the
+/// cache. Bytecode is reference counted and shared with the cache rather than copied.
+/// The inner db is not captured.
+pub fn snapshot_cache_db<ExtDB>(cache_db: &CacheDB<ExtDB>) -> CacheSnapshot {
+    CacheSnapshot {
+        accounts: cache_db.accounts.clone(),
+        contracts: cache_db.contracts.clone(),
+        logs: cache_db.logs.clone(),
+        block_hashes: cache_db.block_hashes.clone(),
+    }
+}
+
+/// Replaces the cached state of `cache_db` with `snapshot`, discarding every change made
+/// since it was taken.
+pub fn restore_cache_db<ExtDB>(cache_db: &mut CacheDB<ExtDB>, snapshot: CacheSnapshot) {
+    cache_db.accounts = snapshot.accounts;
+    cache_db.contracts = snapshot.contracts;
+    cache_db.logs = snapshot.logs;
+    cache_db.block_hashes = snapshot.block_hashes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(balance: u64) -> AccountInfo {
+        AccountInfo {
+            balance: U256::from(balance),
+            ..Default::default()
+        }
+    }
+
+    fn storage<ExtDB>(cache_db: &CacheDB<ExtDB>, address: Address) -> Vec<(u64, u64)> {
+        let mut storage: Vec<_> = cache_db.accounts[&address]
+            .storage
+            .iter()
+            .map(|(index, value)| (index.to::<u64>(), value.to::<u64>()))
+            .collect();
+        storage.sort_unstable();
+        storage
+    }
+
+    #[test]
+    fn merge_prefers_other_on_conflicts() {
+        let (shared, base_only, other_only) = (
+            Address::with_last_byte(1),
+            Address::with_last_byte(2),
+            Address::with_last_byte(3),
+        );
+        let mut base = CacheDB::new(EmptyDB::new());
+        base.insert_account_info(shared, account(1));
+        base.insert_account_info(base_only, account(2));
+        for (index, value) in [(1, 10), (2, 20)] {
+            base.insert_account_storage(shared, U256::from(index), U256::from(value))
+                .unwrap();
+        }
+        base.block_hashes
+            .insert(U256::from(1), B256::repeat_byte(1));
+        base.block_hashes
+            .insert(U256::from(2), B256::repeat_byte(2));
+
+        let mut other = CacheDB::new(EmptyDB::new());
+        other.insert_account_info(shared, account(100));
+        other.insert_account_info(other_only, account(3));
+        for (index, value) in [(2, 200), (3, 300)] {
+            other
+                .insert_account_storage(shared, U256::from(index), U256::from(value))
+                .unwrap();
+        }
+        other
+            .block_hashes
+            .insert(U256::from(2), B256::repeat_byte(0x22));
+        let code = Bytecode::new_raw([0x60, 0x01, 0x00].into());
+        other.insert_account_info(Address::with_last_byte(4), AccountInfo::from_bytecode(code));
+
+        merge_cache_dbs(&mut base, &other);
+        assert_eq!(base.accounts.len(), 4);
+        assert_eq!(base.accounts[&shared].info.balance, U256::from(100));
+        assert_eq!(base.accounts[&base_only].info.balance, U256::from(2));
+        assert_eq!(base.accounts[&other_only].info.balance, U256::from(3));
+        assert_eq!(storage(&base, shared), [(1, 10), (2, 200), (3, 300)]);
+        assert_eq!(base.block_hashes[&U256::from(1)], B256::repeat_byte(1));
+        assert_eq!(base.block_hashes[&U256::from(2)], B256::repeat_byte(0x22));
+        assert_eq!(base.contracts, other.contracts);
+    }
+
+    #[test]
+    fn merging_a_cleared_account_replaces_its_storage() {
+        let address = Address::with_last_byte(1);
+        let mut base = CacheDB::new(EmptyDB::new());
+        base.insert_account_info(address, account(1));
+        for index in [1, 2] {
+            base.insert_account_storage(address, U256::from(index), U256::from(index))
+                .unwrap();
+        }
+        let mut other = CacheDB::new(EmptyDB::new());
+        other
+            .replace_account_storage(
+                address,
+                [(U256::from(3), U256::from(3))].into_iter().collect(),
+            )
+            .unwrap();
+        assert_eq!(
+            other.accounts[&address].account_state,
+            AccountState::StorageCleared
+        );
+
+        let mut interned = base.clone();
+        merge_cache_dbs(&mut base, &other);
+        assert_eq!(storage(&base, address), [(3, 3)]);
+        merge_cache_dbs_interned(&mut interned, &other).unwrap();
+        assert_eq!(storage(&interned, address), [(3, 3)]);
+    }
+
+    #[test]
+    fn delta_of_a_missing_account_keeps_its_storage() {
+        let address = Address::with_last_byte(1);
+        let mut base = CacheDB::new(EmptyDB::new());
+        base.insert_account_storage(address, U256::from(1), U256::from(1))
+            .unwrap();
+        let mut current = base.clone();
+        current
+            .insert_account_storage(address, U256::from(2), U256::from(2))
+            .unwrap();
+        assert_eq!(
+            current.accounts[&address].account_state,
+            AccountState::NotExisting
+        );
+
+        let delta = cache_db_delta(&base, &current);
+        merge_cache_dbs(&mut base, &delta);
+        assert_eq!(storage(&base, address), [(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn interned_merge_keeps_one_copy_of_shared_code() {
+        let raw = [0x60, 0x01, 0x00];
+        let code = || Bytecode::new_raw(raw.to_vec().into());
+        let code_hash = code().hash_slow();
+        let (first, second) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let mut base = CacheDB::new(EmptyDB::new());
+        base.insert_account_info(first, AccountInfo::from_bytecode(code()));
+        let mut other = CacheDB::new(EmptyDB::new());
+        other.insert_account_info(second, AccountInfo::from_bytecode(code()));
+
+        merge_cache_dbs_interned(&mut base, &other).unwrap();
+        let interned = base.contracts[&code_hash].original_byte_slice().as_ptr();
+        for address in [first, second] {
+            let code = base.accounts[&address].info.code.as_ref().unwrap();
+            assert_eq!(code.original_byte_slice().as_ptr(), interned);
+        }
+    }
+
+    #[test]
+    fn interned_merge_rejects_mismatched_code() {
+        let code = Bytecode::new_raw([0x60, 0x01, 0x00].to_vec().into());
+        let code_hash = code.hash_slow();
+        let forged = Bytecode::new_raw([0x60, 0x02, 0x00].to_vec().into());
+        let mut base = CacheDB::new(EmptyDB::new());
+        base.contracts.insert(code_hash, code);
+        let mut other = CacheDB::new(EmptyDB::new());
+        other.contracts.insert(code_hash, forged.clone());
+        other.insert_account_info(Address::with_last_byte(1), account(1));
+
+        let result = merge_cache_dbs_interned(&mut base, &other);
+        assert!(matches!(
+            result,
+            Err(ProxyDbError::CorruptCache { code_hash: hash, actual })
+                if hash == code_hash && actual == forged.hash_slow()
+        ));
+        assert!(base.accounts.is_empty());
+    }
+
+    #[test]
+    fn rebuild_fills_contracts_from_inline_code() {
+        let (contract, empty) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let code = Bytecode::new_raw([0x60, 0x01, 0x00].to_vec().into());
+        let mut cache_db = CacheDB::new(EmptyDB::new());
+        cache_db.accounts.insert(
+            contract,
+            DbAccount {
+                info: AccountInfo {
+                    code_hash: B256::ZERO,
+                    code: Some(code.clone()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        cache_db.accounts.insert(
+            empty,
+            DbAccount {
+                info: AccountInfo {
+                    code: Some(Bytecode::new()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let contracts = cache_db.contracts.len();
+
+        rebuild_contracts_map(&mut cache_db);
+        assert_eq!(
+            cache_db.accounts[&contract].info.code_hash,
+            code.hash_slow()
+        );
+        assert_eq!(cache_db.contracts[&code.hash_slow()], code);
+        assert_eq!(cache_db.accounts[&empty].info.code_hash, KECCAK_EMPTY);
+        assert_eq!(cache_db.contracts.len(), contracts + 1);
+    }
+
+    #[test]
+    fn verify_rejects_a_flipped_code_byte() {
+        let code = Bytecode::new_raw([0x60, 0x01, 0x00].to_vec().into());
+        let code_hash = code.hash_slow();
+        let mut cache_db = CacheDB::new(EmptyDB::new());
+        cache_db.contracts.insert(code_hash, code);
+        verify_cache_db(&cache_db).unwrap();
+
+        let flipped = Bytecode::new_raw([0x60, 0x02, 0x00].to_vec().into());
+        cache_db.contracts.insert(code_hash, flipped.clone());
+        assert!(matches!(
+            verify_cache_db(&cache_db),
+            Err(ProxyDbError::CorruptCache { code_hash: hash, actual })
+                if hash == code_hash && actual == flipped.hash_slow()
+        ));
+    }
+
+    #[test]
+    fn addresses_are_sorted_and_include_missing_accounts() {
+        let [first, second, missing] = [1, 2, 3].map(Address::with_last_byte);
+        let mut cache_db = CacheDB::new(EmptyDB::new());
+        cache_db.insert_account_info(second, account(2));
+        cache_db
+            .accounts
+            .insert(missing, DbAccount::new_not_existing());
+        cache_db.insert_account_info(first, account(1));
+
+        assert_eq!(cache_db_addresses(&cache_db), [first, second, missing]);
+        assert!(cache_db_contains(&cache_db, missing));
+        assert!(!cache_db_contains(&cache_db, Address::with_last_byte(4)));
+    }
+
+    #[test]
+    fn size_estimate_counts_each_cached_value() {
+        let mut cache_db = CacheDB::new(EmptyDB::new());
+        // The two empty contracts every cache starts with.
+        assert_eq!(estimate_cache_db_size(&cache_db), 64);
+
+        let address = Address::with_last_byte(1);
+        cache_db.insert_account_info(address, account(1));
+        for index in [1, 2] {
+            cache_db
+                .insert_account_storage(address, U256::from(index), U256::from(index))
+                .unwrap();
+        }
+        cache_db
+            .block_hashes
+            .insert(U256::from(1), B256::repeat_byte(1));
+        let code = Bytecode::new_raw([0x60, 0x01, 0x00].to_vec().into());
+        cache_db.contracts.insert(code.hash_slow(), code);
+        assert_eq!(
+            estimate_cache_db_size(&cache_db),
+            64 + (92 + 2 * 64) + 64 + (32 + 3)
+        );
+    }
+
+    #[test]
+    fn dump_lists_sorted_accounts_and_slots() {
+        let (first, second) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let code = Bytecode::new_raw([0x60, 0x01, 0x00].to_vec().into());
+        let mut cache_db = CacheDB::new(EmptyDB::new());
+        cache_db.insert_account_info(second, AccountInfo::from_bytecode(code.clone()));
+        cache_db.insert_account_info(first, account(0x10));
+        for index in [2, 1] {
+            cache_db
+                .insert_account_storage(first, U256::from(index), U256::from(index * 0x10))
+                .unwrap();
+        }
+
+        let lines: Vec<_> = dump_cache_db(&cache_db).lines().map(String::from).collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with(&format!("{first} ")));
+        assert!(lines[0].contains("balance=0x10 nonce=0"));
+        assert_eq!(lines[1..3], ["    0x1 => 0x10", "    0x2 => 0x20"]);
+        assert!(lines[3].starts_with(&format!("{second} ")));
+        assert!(lines[3].contains(&format!("code_hash={} code_len=3", code.hash_slow())));
+    }
+
+    #[test]
+    fn restoring_a_snapshot_discards_later_changes() {
+        let address = Address::with_last_byte(1);
+        let mut cache_db = CacheDB::new(EmptyDB::new());
+        cache_db.insert_account_info(address, account(1));
+        cache_db
+            .insert_account_storage(address, U256::from(1), U256::from(1))
+            .unwrap();
+        let fingerprint = cache_db_fingerprint(&cache_db);
+        let snapshot = snapshot_cache_db(&cache_db);
+
+        cache_db.insert_account_info(address, account(2));
+        cache_db
+            .insert_account_storage(address, U256::from(2), U256::from(2))
+            .unwrap();
+        cache_db.insert_account_info(Address::with_last_byte(2), account(3));
+        cache_db
+            .block_hashes
+            .insert(U256::from(1), B256::repeat_byte(1));
+        cache_db.logs.push(Log::default());
+        assert_ne!(cache_db_fingerprint(&cache_db), fingerprint);
+
+        restore_cache_db(&mut cache_db, snapshot);
+        assert_eq!(cache_db_fingerprint(&cache_db), fingerprint);
+        assert_eq!(storage(&cache_db, address), [(1, 1)]);
+        assert!(cache_db.logs.is_empty());
+    }
+
+    #[test]
+    fn subset_keeps_listed_accounts_and_their_code() {
+        let [kept, dropped] = [1, 2].map(Address::with_last_byte);
+        let code = |byte| Bytecode::new_raw([0x60, byte, 0x00].to_vec().into());
+        let (kept_code, dropped_code) = (code(1), code(2));
+        let mut cache_db = CacheDB::new(EmptyDB::new());
+        cache_db.insert_account_info(kept, AccountInfo::from_bytecode(kept_code.clone()));
+        cache_db.insert_account_info(dropped, AccountInfo::from_bytecode(dropped_code.clone()));
+        cache_db
+            .insert_account_storage(kept, U256::from(1), U256::from(1))
+            .unwrap();
+        cache_db
+            .block_hashes
+            .insert(U256::from(1), B256::repeat_byte(1));
+        cache_db.logs.push(Log::default());
+
+        let subset = cache_db_subset(&cache_db, &[kept, Address::with_last_byte(3)]);
+        assert_eq!(cache_db_addresses(&subset), [kept]);
+        assert_eq!(storage(&subset, kept), [(1, 1)]);
+        assert_eq!(subset.contracts[&kept_code.hash_slow()], kept_code);
+        assert!(!subset.contracts.contains_key(&dropped_code.hash_slow()));
+        assert_eq!(subset.block_hashes, cache_db.block_hashes);
+        assert!(subset.logs.is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
+
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder, Compression};
+use revm::db::{CacheDB, EmptyDB};
+
+use crate::{
+    cache_db_from_bytes, cache_db_to_bytes, load_cache_db_from_reader_bincode,
+    persistence::{detach, save_atomically},
+    save_cache_db_to_writer_bincode, ProxyDbError,
+};
+
+/// A file format for caches, see [`save_cache_db_with_codec`].
+///
+/// Implement this to store caches in a format the crate does not ship, such as
+/// MessagePack or CBOR, and report its failures as [`ProxyDbError::Codec`].
+pub trait CacheCodec {
+    fn encode(&self, cache_db: &CacheDB<EmptyDB>) -> Result<Vec<u8>, ProxyDbError>;
+
+    fn decode(&self, bytes: &[u8]) -> Result<CacheDB<EmptyDB>, ProxyDbError>;
+}
+
+/// The versioned JSON layout of [`cache_db_to_bytes`], the default format.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl CacheCodec for JsonCodec {
+    fn encode(&self, cache_db: &CacheDB<EmptyDB>) -> Result<Vec<u8>, ProxyDbError> {
+        cache_db_to_bytes(cache_db)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+        cache_db_from_bytes(bytes)
+    }
+}
+
+/// The versioned bincode layout of [`save_cache_db_to_writer_bincode`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl CacheCodec for BincodeCodec {
+    fn encode(&self, cache_db: &CacheDB<EmptyDB>) -> Result<Vec<u8>, ProxyDbError> {
+        let mut bytes = Vec::new();
+        save_cache_db_to_writer_bincode(&mut bytes, cache_db)?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+        load_cache_db_from_reader_bincode(bytes)
+    }
+}
+
+/// Gzips the output of another codec.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GzipCodec<C>(pub C);
+
+impl<C: CacheCodec> CacheCodec for GzipCodec<C> {
+    fn encode(&self, cache_db: &CacheDB<EmptyDB>) -> Result<Vec<u8>, ProxyDbError> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.0.encode(cache_db)?)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(bytes).read_to_end(&mut decoded)?;
+        self.0.decode(&decoded)
+    }
+}
+
+/// Saves `cache_db` to `path` in the format of `codec`, replacing the file atomically.
+/// The cache is copied without its inner db first.
+pub fn save_cache_db_with_codec<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+    codec: &impl CacheCodec,
+) -> Result<(), ProxyDbError> {
+    let bytes = codec.encode(&detach(cache_db))?;
+    save_atomically(path.as_ref(), |writer| Ok(writer.write_all(&bytes)?))
+}
+
+pub fn load_cache_db_with_codec(
+    path: impl AsRef<Path>,
+    codec: &impl CacheCodec,
+) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+    codec.decode(&fs::read(path)?)
+}
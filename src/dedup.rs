@@ -0,0 +1,79 @@
+use std::sync::Mutex;
+
+use revm::{
+    db::CacheDB,
+    primitives::{Address, U256},
+};
+
+use crate::{bounded::KeySet, NewFetch};
+
+/// Keys already emitted by a deduplicating [`RecordingDb`](crate::RecordingDb).
+#[derive(Debug, Default)]
+pub(crate) struct Dedup {
+    accounts: Mutex<KeySet<Address>>,
+    storage: Mutex<KeySet<(Address, U256)>>,
+    block_numbers: Mutex<KeySet<u64>>,
+}
+
+impl Dedup {
+    /// Remembers at most `capacity` keys of each kind, forgetting the least recently
+    /// seen ones first.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            accounts: Mutex::new(KeySet::with_capacity(capacity)),
+            storage: Mutex::new(KeySet::with_capacity(capacity)),
+            block_numbers: Mutex::new(KeySet::with_capacity(capacity)),
+        }
+    }
+
+    /// Marks every account, storage slot and block number held by `cache_db` as seen.
+    pub(crate) fn prime<ExtDB>(&self, cache_db: &CacheDB<ExtDB>) {
+        for (address, account) in &cache_db.accounts {
+            insert(&self.accounts, *address);
+            for index in account.storage.keys() {
+                insert(&self.storage, (*address, *index));
+            }
+        }
+        for number in cache_db.block_hashes.keys() {
+            if let Ok(number) = u64::try_from(*number) {
+                insert(&self.block_numbers, number);
+            }
+        }
+    }
+
+    /// Forgets every key seen so far.
+    pub(crate) fn clear(&self) {
+        clear(&self.accounts);
+        clear(&self.storage);
+        clear(&self.block_numbers);
+    }
+
+    /// Returns `true` the first time the key of `fetch` is seen.
+    pub(crate) fn first_seen(&self, fetch: &NewFetch) -> bool {
+        match fetch {
+            NewFetch::Basic { address, .. } | NewFetch::BasicMissing { address } => {
+                insert(&self.accounts, *address)
+            }
+            NewFetch::Storage { address, index, .. } => insert(&self.storage, (*address, *index)),
+            // Zero hashes for numbers outside the 256-block window are still recorded once,
+            // otherwise a replayed cache would ask its inner db instead of answering zero.
+            NewFetch::BlockHash { number, .. } => insert(&self.block_numbers, *number),
+            NewFetch::CodeByHash { .. }
+            | NewFetch::Error { .. }
+            | NewFetch::CacheHit { .. }
+            | NewFetch::BasicWrite { .. }
+            | NewFetch::StorageWrite { .. }
+            | NewFetch::Summary { .. } => true,
+        }
+    }
+}
+
+fn insert<K: Eq + std::hash::Hash>(set: &Mutex<KeySet<K>>, key: K) -> bool {
+    set.lock().map(|mut set| set.insert(key)).unwrap_or(true)
+}
+
+fn clear<K: Eq + std::hash::Hash>(set: &Mutex<KeySet<K>>) {
+    if let Ok(mut set) = set.lock() {
+        set.clear();
+    }
+}
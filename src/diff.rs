@@ -0,0 +1,151 @@
+use revm::{
+    db::CacheDB,
+    primitives::{AccountInfo, Address, U256},
+};
+
+/// The differences between two caches, see [`diff_cache_dbs`]. Every list is sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheDiff {
+    /// Accounts only present in the new cache.
+    pub added: Vec<Address>,
+    /// Accounts only present in the old cache.
+    pub removed: Vec<Address>,
+    /// Accounts present in both caches whose info or storage differs.
+    pub changed: Vec<AccountDiff>,
+}
+
+impl CacheDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The differences of one account present in both caches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub address: Address,
+    /// The old and new info, if the balance, nonce or code hash changed.
+    pub info: Option<(AccountInfo, AccountInfo)>,
+    /// Slots only present in the new cache, with their value.
+    pub added_slots: Vec<(U256, U256)>,
+    /// Slots only present in the old cache, with their value.
+    pub removed_slots: Vec<(U256, U256)>,
+    /// Slots present in both caches, with their old and new value.
+    pub changed_slots: Vec<(U256, U256, U256)>,
+}
+
+impl AccountDiff {
+    fn is_empty(&self) -> bool {
+        self.info.is_none()
+            && self.added_slots.is_empty()
+            && self.removed_slots.is_empty()
+            && self.changed_slots.is_empty()
+    }
+}
+
+/// Compares the accounts and storage of `old` and `new`.
+///
+/// Only the cached state is compared: contracts, logs and block hashes are ignored, and
+/// so is anything `new`'s inner db would return for keys it does not hold.
+pub fn diff_cache_dbs<ExtDB, OtherDB>(old: &CacheDB<ExtDB>, new: &CacheDB<OtherDB>) -> CacheDiff {
+    let mut diff = CacheDiff::default();
+    for (address, old_account) in &old.accounts {
+        let Some(new_account) = new.accounts.get(address) else {
+            diff.removed.push(*address);
+            continue;
+        };
+        let mut account_diff = AccountDiff {
+            address: *address,
+            info: (old_account.info != new_account.info)
+                .then(|| (old_account.info.clone(), new_account.info.clone())),
+            added_slots: Vec::new(),
+            removed_slots: Vec::new(),
+            changed_slots: Vec::new(),
+        };
+        for (index, old_value) in &old_account.storage {
+            match new_account.storage.get(index) {
+                None => account_diff.removed_slots.push((*index, *old_value)),
+                Some(new_value) if new_value != old_value => account_diff
+                    .changed_slots
+                    .push((*index, *old_value, *new_value)),
+                Some(_) => {}
+            }
+        }
+        for (index, new_value) in &new_account.storage {
+            if !old_account.storage.contains_key(index) {
+                account_diff.added_slots.push((*index, *new_value));
+            }
+        }
+        if !account_diff.is_empty() {
+            account_diff.added_slots.sort_unstable();
+            account_diff.removed_slots.sort_unstable();
+            account_diff.changed_slots.sort_unstable();
+            diff.changed.push(account_diff);
+        }
+    }
+    diff.added = new
+        .accounts
+        .keys()
+        .filter(|address| !old.accounts.contains_key(*address))
+        .copied()
+        .collect();
+    diff.added.sort_unstable();
+    diff.removed.sort_unstable();
+    diff.changed.sort_unstable_by_key(|account| account.address);
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::db::EmptyDB;
+
+    use super::*;
+
+    fn account(balance: u64) -> AccountInfo {
+        AccountInfo {
+            balance: U256::from(balance),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diff_reports_every_category() {
+        let [unchanged, removed, added, changed] = [1, 2, 3, 4].map(Address::with_last_byte);
+        let slot = |index: u64, value: u64| (U256::from(index), U256::from(value));
+        let mut old = CacheDB::new(EmptyDB::new());
+        let mut new = CacheDB::new(EmptyDB::new());
+        for cache_db in [&mut old, &mut new] {
+            cache_db.insert_account_info(unchanged, account(1));
+            cache_db
+                .insert_account_storage(unchanged, U256::from(1), U256::from(1))
+                .unwrap();
+        }
+        old.insert_account_info(removed, account(2));
+        new.insert_account_info(added, account(3));
+        old.insert_account_info(changed, account(4));
+        new.insert_account_info(changed, account(40));
+        for (index, value) in [(1, 1), (2, 2), (3, 3)] {
+            old.insert_account_storage(changed, U256::from(index), U256::from(value))
+                .unwrap();
+        }
+        for (index, value) in [(1, 1), (3, 30), (4, 4)] {
+            new.insert_account_storage(changed, U256::from(index), U256::from(value))
+                .unwrap();
+        }
+
+        let diff = diff_cache_dbs(&old, &new);
+        assert_eq!(diff.added, [added]);
+        assert_eq!(diff.removed, [removed]);
+        assert_eq!(
+            diff.changed,
+            [AccountDiff {
+                address: changed,
+                info: Some((account(4), account(40))),
+                added_slots: vec![slot(4, 4)],
+                removed_slots: vec![slot(2, 2)],
+                changed_slots: vec![(U256::from(3), U256::from(3), U256::from(30))],
+            }]
+        );
+        assert!(diff_cache_dbs(&new, &new).is_empty());
+    }
+}
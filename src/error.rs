@@ -0,0 +1,105 @@
+use std::{fmt, io};
+
+use revm::primitives::{Address, B256, U256};
+
+use crate::FetchKey;
+
+/// Errors returned by the cache persistence helpers and [`ReplayDb`](crate::ReplayDb).
+#[derive(Debug)]
+pub enum ProxyDbError {
+    Io(io::Error),
+    #[cfg(feature = "persistence")]
+    Serde(serde_json::Error),
+    #[cfg(feature = "persistence")]
+    Bincode(bincode::Error),
+    /// A blocking serialization task panicked or was cancelled.
+    Join(tokio::task::JoinError),
+    /// The cache was written with a layout version this crate cannot read.
+    UnsupportedVersion(u16),
+    /// A contract's bytecode does not hash to the code hash it is stored under.
+    CorruptCache {
+        code_hash: B256,
+        actual: B256,
+    },
+    /// A replay looked up a key that was never captured.
+    NotCaptured(FetchKey),
+    /// A storage slot was recorded for an account no fetch covers, see
+    /// [`OrphanStoragePolicy::Reject`](crate::OrphanStoragePolicy::Reject).
+    OrphanStorage {
+        address: Address,
+        index: U256,
+    },
+    /// A custom [`CacheCodec`](crate::CacheCodec) failed.
+    Codec(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for ProxyDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "io error: {err}"),
+            #[cfg(feature = "persistence")]
+            Self::Serde(err) => write!(f, "json error: {err}"),
+            #[cfg(feature = "persistence")]
+            Self::Bincode(err) => write!(f, "bincode error: {err}"),
+            Self::Join(err) => write!(f, "blocking task failed: {err}"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported cache format version {version}")
+            }
+            Self::CorruptCache { code_hash, actual } => {
+                write!(f, "bytecode stored under {code_hash} hashes to {actual}")
+            }
+            Self::NotCaptured(key) => write!(f, "{key} was not captured"),
+            Self::OrphanStorage { address, index } => {
+                write!(
+                    f,
+                    "slot {index} of {address} was recorded without its account"
+                )
+            }
+            Self::Codec(err) => write!(f, "codec error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ProxyDbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            #[cfg(feature = "persistence")]
+            Self::Serde(err) => Some(err),
+            #[cfg(feature = "persistence")]
+            Self::Bincode(err) => Some(err),
+            Self::Join(err) => Some(err),
+            Self::Codec(err) => Some(err.as_ref()),
+            Self::UnsupportedVersion(_)
+            | Self::CorruptCache { .. }
+            | Self::NotCaptured(_)
+            | Self::OrphanStorage { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProxyDbError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl From<serde_json::Error> for ProxyDbError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serde(err)
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl From<bincode::Error> for ProxyDbError {
+    fn from(err: bincode::Error) -> Self {
+        Self::Bincode(err)
+    }
+}
+
+impl From<tokio::task::JoinError> for ProxyDbError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Self::Join(err)
+    }
+}
@@ -0,0 +1,132 @@
+use revm::{
+    primitives::{AccountInfo, Address, Bytecode, B256, U256},
+    DatabaseRef,
+};
+
+/// Serves lookups from a local `primary` db, backfilling its misses from `secondary`.
+///
+/// A lookup falls back when the primary fails, whatever the error, or when it answers
+/// the value a db returns for something it does not hold: `None` for an account, an
+/// empty bytecode, a zero storage slot or a zero block hash. Genuinely zero slots are
+/// therefore always read from the secondary. Errors of the secondary are returned as is.
+///
+/// To record only the reads that reached the secondary, wrap the secondary in the
+/// [`RecordingDb`](crate::RecordingDb) rather than the whole `FallbackDb`.
+#[derive(Debug, Clone)]
+pub struct FallbackDb<Primary, Secondary> {
+    primary: Primary,
+    secondary: Secondary,
+}
+
+impl<Primary, Secondary> FallbackDb<Primary, Secondary> {
+    pub fn new(primary: Primary, secondary: Secondary) -> Self {
+        Self { primary, secondary }
+    }
+
+    pub fn primary(&self) -> &Primary {
+        &self.primary
+    }
+
+    pub fn primary_mut(&mut self) -> &mut Primary {
+        &mut self.primary
+    }
+
+    pub fn secondary(&self) -> &Secondary {
+        &self.secondary
+    }
+
+    pub fn secondary_mut(&mut self) -> &mut Secondary {
+        &mut self.secondary
+    }
+
+    pub fn into_parts(self) -> (Primary, Secondary) {
+        (self.primary, self.secondary)
+    }
+}
+
+fn hit<T>(result: Result<T, impl Sized>, is_miss: impl FnOnce(&T) -> bool) -> Option<T> {
+    result.ok().filter(|value| !is_miss(value))
+}
+
+impl<Primary: DatabaseRef, Secondary: DatabaseRef> DatabaseRef for FallbackDb<Primary, Secondary> {
+    type Error = Secondary::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        match hit(self.primary.basic_ref(address), Option::is_none) {
+            Some(account_info) => Ok(account_info),
+            None => self.secondary.basic_ref(address),
+        }
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        match hit(self.primary.code_by_hash_ref(code_hash), Bytecode::is_empty) {
+            Some(bytecode) => Ok(bytecode),
+            None => self.secondary.code_by_hash_ref(code_hash),
+        }
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        match hit(self.primary.storage_ref(address, index), U256::is_zero) {
+            Some(value) => Ok(value),
+            None => self.secondary.storage_ref(address, index),
+        }
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        match hit(self.primary.block_hash_ref(number), B256::is_zero) {
+            Some(hash) => Ok(hash),
+            None => self.secondary.block_hash_ref(number),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::{FlakyDb, MockDb};
+
+    fn account(balance: u64) -> AccountInfo {
+        AccountInfo {
+            balance: U256::from(balance),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn primary_misses_are_read_from_the_secondary() {
+        let (local, remote) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let db = FallbackDb::new(
+            MockDb::new().with_account(local, account(1)).with_storage(
+                local,
+                U256::from(1),
+                U256::from(10),
+            ),
+            MockDb::new()
+                .with_account(local, account(2))
+                .with_account(remote, account(3))
+                .with_storage(local, U256::from(1), U256::from(20))
+                .with_storage(local, U256::from(2), U256::from(30)),
+        );
+
+        assert_eq!(db.basic_ref(local), Ok(Some(account(1))));
+        assert_eq!(db.basic_ref(remote), Ok(Some(account(3))));
+        assert_eq!(db.storage_ref(local, U256::from(1)), Ok(U256::from(10)));
+        assert_eq!(db.storage_ref(local, U256::from(2)), Ok(U256::from(30)));
+        let secondary = db.secondary().calls();
+        assert_eq!((secondary.basic_reads, secondary.storage_reads), (1, 1));
+    }
+
+    #[test]
+    fn primary_errors_are_read_from_the_secondary() {
+        let address = Address::with_last_byte(1);
+        let db = FallbackDb::new(
+            FlakyDb::new(MockDb::new().with_account(address, account(1)), 1),
+            MockDb::new().with_account(address, account(2)),
+        );
+
+        assert_eq!(db.basic_ref(address), Ok(Some(account(2))));
+        assert_eq!(db.basic_ref(address), Ok(Some(account(1))));
+        assert_eq!(db.primary().attempts(), 2);
+        assert_eq!(db.secondary().calls().basic_reads, 1);
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Mutex;
+
+use revm::primitives::{Address, HashSet, B256};
+
+use crate::{FetchKey, NewFetch};
+
+/// Restricts recording to the accounts accepted by a predicate.
+///
+/// Code has no address of its own, so a `CodeByHash` fetch is only recorded when an
+/// accepted account with that code hash has already been recorded.
+pub(crate) struct AddressFilter {
+    predicate: Box<dyn Fn(&Address) -> bool + Send + Sync>,
+    code_hashes: Mutex<HashSet<B256>>,
+}
+
+impl AddressFilter {
+    pub(crate) fn new(predicate: impl Fn(&Address) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            predicate: Box::new(predicate),
+            code_hashes: Default::default(),
+        }
+    }
+
+    /// Forgets the code hashes of the accounts recorded so far.
+    pub(crate) fn clear(&self) {
+        if let Ok(mut code_hashes) = self.code_hashes.lock() {
+            code_hashes.clear();
+        }
+    }
+
+    pub(crate) fn allows(&self, fetch: &NewFetch) -> bool {
+        match fetch {
+            NewFetch::Basic {
+                address,
+                account_info,
+            } => {
+                let allowed = (self.predicate)(address);
+                if allowed {
+                    if let Ok(mut code_hashes) = self.code_hashes.lock() {
+                        code_hashes.insert(account_info.code_hash);
+                    }
+                }
+                allowed
+            }
+            NewFetch::BasicMissing { address }
+            | NewFetch::Storage { address, .. }
+            | NewFetch::BasicWrite { address, .. }
+            | NewFetch::StorageWrite { address, .. } => (self.predicate)(address),
+            NewFetch::CodeByHash { code_hash, .. } => self
+                .code_hashes
+                .lock()
+                .is_ok_and(|code_hashes| code_hashes.contains(code_hash)),
+            NewFetch::Error { address, .. } => address.as_ref().is_none_or(&self.predicate),
+            NewFetch::Summary { .. } => true,
+            NewFetch::CacheHit { key } => match key {
+                FetchKey::Basic(address) | FetchKey::Storage(address, _) => {
+                    (self.predicate)(address)
+                }
+                FetchKey::CodeByHash(_) | FetchKey::BlockHash(_) => true,
+            },
+            NewFetch::BlockHash { .. } => true,
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use alloy_eips::BlockId;
+use alloy_provider::{Network, Provider};
+use alloy_transport::Transport;
+use revm::db::{AlloyDB, CacheDB};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{NewFetch, RecordingDb};
+
+/// A forked state: a `CacheDB` over a recording RPC-backed db, see [`fork_at_block`].
+pub type ForkDb<T, N, P> = CacheDB<RecordingDb<AlloyDB<T, N, P>>>;
+
+/// The usual fork setup: a `CacheDB` over a [`RecordingDb`] over an RPC `provider`,
+/// with every read pinned to `block_number` and recorded into the returned channel.
+///
+/// Returns `None` outside of a multi-threaded tokio runtime, like [`AlloyDB::new`].
+pub fn fork_at_block<T, N, P>(
+    provider: P,
+    block_number: u64,
+) -> Option<(ForkDb<T, N, P>, UnboundedReceiver<NewFetch>)>
+where
+    T: Transport + Clone,
+    N: Network,
+    P: Provider<T, N>,
+{
+    let alloy_db = AlloyDB::new(provider, BlockId::number(block_number))?;
+    let (proxy, receiver) = RecordingDb::new_with_channel(alloy_db);
+    Some((CacheDB::new(proxy), receiver))
+}
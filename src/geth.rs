@@ -0,0 +1,108 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, Read},
+};
+
+use revm::{
+    db::{AccountState, CacheDB, EmptyDB},
+    primitives::{AccountInfo, Address, Bytecode, Bytes, B256, U256},
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::ProxyDbError;
+
+/// Either a `debug_dumpBlock` style dump or a bare genesis `alloc` map.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Dump {
+    Wrapped {
+        accounts: BTreeMap<Address, Account>,
+    },
+    Alloc(BTreeMap<Address, Account>),
+}
+
+/// Numbers are accepted as JSON numbers, decimal strings or hex quantities.
+#[derive(Deserialize)]
+struct Account {
+    #[serde(default)]
+    balance: U256,
+    #[serde(default)]
+    nonce: U256,
+    #[serde(default)]
+    code: Bytes,
+    #[serde(default)]
+    storage: BTreeMap<U256, U256>,
+}
+
+/// Builds an offline cache from a geth state dump.
+///
+/// Accepts both the `debug_dumpBlock` layout (`{ "accounts": { address: account } }`) and
+/// a bare genesis `alloc` map. Code is hashed into the contracts map; any `codeHash` or
+/// storage `root` in the dump is ignored.
+///
+/// Storage keys are parsed as numbers, so a minimal (`0x1`), zero-padded or decimal key
+/// all name the same slot. When a dump lists one slot under several encodings, the last
+/// one wins.
+pub fn cache_db_from_geth_dump<R: Read>(reader: R) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+    let (Dump::Wrapped { accounts } | Dump::Alloc(accounts)) =
+        serde_json::from_reader(BufReader::new(reader))?;
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    for (address, account) in accounts {
+        let nonce = u64::try_from(account.nonce).map_err(|_| {
+            <serde_json::Error as serde::de::Error>::custom(format!(
+                "nonce of {address} does not fit in a u64"
+            ))
+        })?;
+        let bytecode = Bytecode::new_raw(account.code);
+        let code_hash = bytecode.hash_slow();
+        cache_db.insert_account_info(
+            address,
+            AccountInfo::new(account.balance, nonce, code_hash, bytecode),
+        );
+        cache_db
+            .accounts
+            .entry(address)
+            .or_default()
+            .storage
+            .extend(account.storage);
+    }
+    Ok(cache_db)
+}
+
+/// Exports the accounts of `cache_db` as a genesis `alloc` object geth or anvil can load.
+///
+/// Balances and nonces are hex quantities and storage slots 32-byte words. Code is taken
+/// from the account or, failing that, from the contracts map. Accounts cached as not
+/// existing are left out, as are zero nonces, empty code and empty storage.
+pub fn cache_db_to_genesis_alloc<ExtDB>(cache_db: &CacheDB<ExtDB>) -> Value {
+    let mut alloc = Map::new();
+    for (address, account) in &cache_db.accounts {
+        if account.account_state == AccountState::NotExisting {
+            continue;
+        }
+        let info = &account.info;
+        let mut entry = Map::new();
+        entry.insert("balance".into(), json!(info.balance));
+        if info.nonce != 0 {
+            entry.insert("nonce".into(), json!(format!("{:#x}", info.nonce)));
+        }
+        let code = info
+            .code
+            .as_ref()
+            .or_else(|| cache_db.contracts.get(&info.code_hash));
+        if let Some(code) = code.filter(|code| !code.is_empty()) {
+            entry.insert("code".into(), json!(code.original_bytes()));
+        }
+        if !account.storage.is_empty() {
+            let storage: Map<String, Value> = account
+                .storage
+                .iter()
+                .map(|(index, value)| (B256::from(*index).to_string(), json!(B256::from(*value))))
+                .collect();
+            entry.insert("storage".into(), Value::Object(storage));
+        }
+        alloc.insert(format!("{address:#x}"), Value::Object(entry));
+    }
+    Value::Object(alloc)
+}
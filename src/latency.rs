@@ -0,0 +1,109 @@
+use std::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of the histogram buckets, slower calls land in a final open bucket.
+const BUCKET_BOUNDS: [Duration; 6] = [
+    Duration::from_micros(10),
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+];
+
+/// How long the inner db took to answer the calls of a [`RecordingDb`](crate::RecordingDb), see
+/// [`RecordingDb::with_latency_histogram`](crate::RecordingDb::with_latency_histogram).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Calls per bucket, from fastest to slowest.
+    pub buckets: Vec<LatencyBucket>,
+    /// Time spent in all calls together.
+    pub total: Duration,
+}
+
+impl LatencyHistogram {
+    /// Number of calls measured.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|bucket| bucket.count).sum()
+    }
+
+    /// Mean call duration, or zero if nothing was measured.
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total.as_nanos() / count as u128) as u64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyBucket {
+    /// Inclusive upper bound, `None` for the bucket of calls slower than every bound.
+    pub upper_bound: Option<Duration>,
+    pub count: u64,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Latency {
+    counts: [AtomicU64; BUCKET_BOUNDS.len() + 1],
+    total_nanos: AtomicU64,
+}
+
+impl Latency {
+    fn observe(&self, elapsed: Duration) {
+        let bucket = BUCKET_BOUNDS
+            .iter()
+            .position(|bound| elapsed <= *bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reset(&self) {
+        for count in &self.counts {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.total_nanos.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: self
+                .counts
+                .iter()
+                .enumerate()
+                .map(|(i, count)| LatencyBucket {
+                    upper_bound: BUCKET_BOUNDS.get(i).copied(),
+                    count: count.load(Ordering::Relaxed),
+                })
+                .collect(),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Runs `call`, timing it only if `latency` is set.
+pub(crate) fn time<T>(latency: Option<&Latency>, call: impl FnOnce() -> T) -> T {
+    let Some(latency) = latency else {
+        return call();
+    };
+    let start = Instant::now();
+    let result = call();
+    latency.observe(start.elapsed());
+    result
+}
+
+/// Async counterpart of [`time`].
+pub(crate) async fn time_async<F: Future>(latency: Option<&Latency>, call: F) -> F::Output {
+    let Some(latency) = latency else {
+        return call.await;
+    };
+    let start = Instant::now();
+    let result = call.await;
+    latency.observe(start.elapsed());
+    result
+}
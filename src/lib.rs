@@ -1,114 +1,1083 @@
+mod analysis;
+mod async_db;
+mod bounded;
+mod bundle;
+mod cache;
+#[cfg(feature = "persistence")]
+mod codec;
+mod dedup;
+mod diff;
+mod error;
+mod fallback;
+mod filter;
+#[cfg(feature = "alloy")]
+mod fork;
+#[cfg(feature = "persistence")]
+mod geth;
+mod latency;
+mod limit;
+#[cfg(feature = "test-util")]
+mod mock;
+#[cfg(feature = "persistence")]
+mod ndjson;
+#[cfg(feature = "persistence")]
+mod persistence;
+mod prefetch;
+mod read_cache;
+mod replay;
+mod replay_db;
+mod retry;
+mod sink;
+mod stats;
+
 use std::{
-    fs::File,
-    io::{Read, Write},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use revm::{
     db::{CacheDB, EmptyDB},
-    primitives::{AccountInfo, Address, Bytecode, B256, U256},
-    DatabaseRef,
+    primitives::{Account, AccountInfo, Address, Bytecode, HashMap, B256, U256},
+    Database, DatabaseCommit, DatabaseRef,
+};
+#[cfg(feature = "persistence")]
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender};
+
+pub use analysis::{
+    access_list_from_fetches, code_size_histogram, group_fetches_by_account, AccessReport,
+    AccountAccess,
+};
+pub use async_db::AsyncDatabaseRef;
+pub use bundle::fetches_to_bundle_state;
+pub use cache::{
+    cache_db_addresses, cache_db_contains, cache_db_delta, cache_db_fingerprint, cache_db_subset,
+    dump_cache_db, estimate_cache_db_size, flatten_cache_db, merge_cache_dbs,
+    merge_cache_dbs_interned, rebuild_contracts_map, restore_cache_db, snapshot_cache_db,
+    verify_cache_db, CacheLayers, CacheSnapshot,
+};
+#[cfg(feature = "persistence")]
+pub use codec::{
+    load_cache_db_with_codec, save_cache_db_with_codec, BincodeCodec, CacheCodec, GzipCodec,
+    JsonCodec,
+};
+use dedup::Dedup;
+pub use diff::{diff_cache_dbs, AccountDiff, CacheDiff};
+pub use error::ProxyDbError;
+pub use fallback::FallbackDb;
+use filter::AddressFilter;
+#[cfg(feature = "alloy")]
+pub use fork::{fork_at_block, ForkDb};
+#[cfg(feature = "persistence")]
+pub use geth::{cache_db_from_geth_dump, cache_db_to_genesis_alloc};
+use latency::Latency;
+pub use latency::{LatencyBucket, LatencyHistogram};
+use limit::RecordLimit;
+#[cfg(all(feature = "test-util", feature = "persistence"))]
+pub use mock::record_and_replay;
+#[cfg(feature = "test-util")]
+pub use mock::{FlakyDb, MockDb, Unavailable};
+#[cfg(feature = "persistence")]
+pub use ndjson::{
+    load_fetches_from_ndjson, spawn_ndjson_writer, spawn_ndjson_writer_compressed,
+    spawn_ndjson_writer_with_interval,
+};
+#[cfg(feature = "persistence")]
+pub use persistence::{
+    append_fetches_to_file, cache_db_from_bytes, cache_db_to_bytes, cache_db_to_bytes_with_format,
+    load_bundle, load_cache_db_applying, load_cache_db_from_file, load_cache_db_from_file_async,
+    load_cache_db_from_file_bincode, load_cache_db_from_file_streaming, load_cache_db_from_reader,
+    load_cache_db_from_reader_bincode, load_cache_db_from_reader_streaming, load_cache_db_sharded,
+    load_cache_db_with_deltas, load_cache_db_with_fork, save_bundle, save_cache_db_delta,
+    save_cache_db_relative_to, save_cache_db_sharded, save_cache_db_subset, save_cache_db_to_file,
+    save_cache_db_to_file_async, save_cache_db_to_file_bincode,
+    save_cache_db_to_file_bincode_compressed, save_cache_db_to_file_compressed,
+    save_cache_db_to_file_with_format, save_cache_db_to_writer, save_cache_db_to_writer_bincode,
+    save_cache_db_with_fork, save_flattened_cache_db_to_file, shard_index, BundleMetadata,
+    ForkDescriptor, NumberFormat, SimulationBundle, CACHE_FORMAT_VERSION,
+};
+pub use prefetch::{prefetch_into_cache_db, prefetch_into_cache_db_async, PrefetchErrorPolicy};
+use read_cache::ReadCache;
+pub use replay::{
+    apply_fetches_to_cache_db, apply_fetches_to_cache_db_with_policy, cache_db_from_fetches,
+    cache_db_from_fetches_with_policy, divergence_report, fetches_to_ops, post_state_from_fetches,
+    verify_replay, CacheOp, DivergenceReport, OrphanStoragePolicy, ReplayMismatch,
 };
-use serde::de::DeserializeOwned;
-use tokio::sync::mpsc::UnboundedSender;
+pub use replay_db::{assert_offline, OfflineDb, ReplayDb};
+pub use retry::{RetryDb, RetryError};
+pub use sink::{
+    drain_receiver, BackpressurePolicy, BatchSender, BoundedSender, DynSink, FanOutSender,
+    FetchBuffer, FetchCallback, FetchSink, NoopSink, SequencedSender, TimedSender,
+};
+use stats::Stats;
+pub use stats::{DbStats, UniqueStats};
 
-#[derive(Debug)]
+/// A value read from the inner db.
+///
+/// With the `persistence` feature, serializes with revm's own serde representation of
+/// its primitives, so events can be persisted or sent across process boundaries and read
+/// back unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
 pub enum NewFetch {
+    /// `account_info` keeps any code the inner db returned inline, so replaying this
+    /// fetch alone also fills the contracts map.
     Basic {
         address: Address,
         account_info: AccountInfo,
     },
+    /// The inner db has no account at `address`.
+    BasicMissing {
+        address: Address,
+    },
     Storage {
         address: Address,
         index: U256,
         value: U256,
     },
+    CodeByHash {
+        code_hash: B256,
+        bytecode: Bytecode,
+    },
+    BlockHash {
+        number: u64,
+        hash: B256,
+    },
+    /// The read cache answered a lookup for `key` without asking the inner db, see
+    /// [`RecordingDb::record_cache_hits`].
+    CacheHit {
+        key: FetchKey,
+    },
+    /// An account as left by [`DatabaseCommit::commit`], see [`RecordingDb::record_writes`].
+    /// Unlike every other variant, this is a value written rather than read.
+    BasicWrite {
+        address: Address,
+        account_info: AccountInfo,
+    },
+    /// A storage slot as left by a commit, see [`NewFetch::BasicWrite`].
+    StorageWrite {
+        address: Address,
+        index: U256,
+        value: U256,
+    },
+    /// The last event of a capture, carrying the final stats, see [`RecordingDb::finish`].
+    Summary {
+        stats: DbStats,
+    },
+    /// The inner db failed a lookup, with the error it returned rendered as `message`.
+    Error {
+        kind: FetchKind,
+        address: Option<Address>,
+        index: Option<U256>,
+        message: String,
+    },
+}
+
+/// The lookup a failed [`NewFetch::Error`] was for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub enum FetchKind {
+    Basic,
+    Storage,
+    CodeByHash,
+    BlockHash,
+}
+
+/// What a [`RecordingDb`] records when the inner db returns a zero block hash, as it
+/// does for numbers outside the 256-block window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockHashPolicy {
+    /// Record the zero hash like any other, so a replay answers zero too.
+    #[default]
+    Record,
+    /// Record nothing, leaving the lookup to whatever backs the replayed cache.
+    Skip,
+    /// Record a [`NewFetch::Error`] for the lookup instead of its hash.
+    Error,
+}
+
+/// The key a lookup is made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub enum FetchKey {
+    Basic(Address),
+    Storage(Address, U256),
+    CodeByHash(B256),
+    BlockHash(u64),
+}
+
+impl fmt::Display for FetchKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Basic(address) => write!(f, "account {address}"),
+            Self::Storage(address, index) => write!(f, "slot {index} of {address}"),
+            Self::CodeByHash(code_hash) => write!(f, "code {code_hash}"),
+            Self::BlockHash(number) => write!(f, "hash of block {number}"),
+        }
+    }
+}
+
+/// A [`NewFetch`] stamped with the time it completed, see [`RecordingDb::with_timestamps`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct TimedFetch {
+    /// Time elapsed between the start marker and the inner db returning.
+    pub at: Duration,
+    pub fetch: NewFetch,
 }
 
-pub struct ProxyDB<ExtDB> {
-    pub db: ExtDB,
-    pub sender: Option<UnboundedSender<NewFetch>>,
+/// A [`NewFetch`] numbered in emission order, see [`RecordingDb::with_sequence_numbers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct SequencedFetch {
+    /// Starts at 0 and increases by one with every fetch recorded, across all threads.
+    pub seq: u64,
+    pub fetch: NewFetch,
+}
+
+/// A [`RecordingDb`] buffering its fetches in memory, without any channel.
+pub type RecordingProxyDB<ExtDB> = RecordingDb<ExtDB, FetchBuffer>;
+
+/// A [`RecordingDb`] whose sink can be swapped at runtime, see [`RecordingDb::set_dyn_sink`].
+pub type DynRecordingDb<ExtDB> = RecordingDb<ExtDB, DynSink>;
+
+#[deprecated(note = "renamed to `RecordingDb`")]
+pub type ProxyDB<ExtDB, S = UnboundedSender<NewFetch>> = RecordingDb<ExtDB, S>;
+
+/// Wraps a live db and records every value read from it, see [`ReplayDb`] for serving
+/// the recording back.
+///
+/// Clones share their sink, dedup set, stats, filter and read cache with the original,
+/// so fetches from concurrent clones interleave on the same sink and are counted once.
+#[derive(Clone)]
+pub struct RecordingDb<ExtDB, S = UnboundedSender<NewFetch>> {
+    db: ExtDB,
+    sink: Option<S>,
+    dedup: Option<Arc<Dedup>>,
+    skip_zero_storage: bool,
+    stats: Arc<Stats>,
+    filter: Option<Arc<AddressFilter>>,
+    read_cache: Option<Arc<ReadCache>>,
+    record_commits: bool,
+    record_writes: bool,
+    record_cache_hits: bool,
+    latency: Option<Arc<Latency>>,
+    transform: Option<Arc<RecordTransform>>,
+    limit: Option<Arc<RecordLimit>>,
+    block_hash_policy: BlockHashPolicy,
+    mirror: Option<Arc<Mutex<CacheDB<EmptyDB>>>>,
 }
 
-impl<ExtDB> ProxyDB<ExtDB>
+type RecordTransform = dyn Fn(&NewFetch) -> Option<NewFetch> + Send + Sync;
+
+impl<ExtDB> RecordingDb<ExtDB> {
+    pub fn new(db: ExtDB) -> Self {
+        Self {
+            db,
+            sink: None,
+            dedup: None,
+            skip_zero_storage: false,
+            stats: Arc::default(),
+            filter: None,
+            read_cache: None,
+            record_commits: false,
+            record_writes: false,
+            record_cache_hits: false,
+            latency: None,
+            transform: None,
+            limit: None,
+            block_hash_policy: BlockHashPolicy::Record,
+            mirror: None,
+        }
+    }
+
+    /// Creates a `RecordingDb` that records every fetch into a new unbounded channel,
+    /// returning the receiving half alongside it.
+    ///
+    /// ```
+    /// use revm::{db::EmptyDB, primitives::Address, DatabaseRef};
+    /// use revm_proxy_db::{NewFetch, RecordingDb};
+    ///
+    /// let (db, mut receiver) = RecordingDb::new_with_channel(EmptyDB::new());
+    /// db.basic_ref(Address::ZERO).unwrap();
+    ///
+    /// let mut fetches = Vec::new();
+    /// while let Ok(fetch) = receiver.try_recv() {
+    ///     fetches.push(fetch);
+    /// }
+    /// assert_eq!(fetches, [NewFetch::BasicMissing { address: Address::ZERO }]);
+    /// ```
+    pub fn new_with_channel(db: ExtDB) -> (Self, UnboundedReceiver<NewFetch>) {
+        let (sender, receiver) = unbounded_channel();
+        (Self::new(db).with_sender(sender), receiver)
+    }
+
+    /// Creates a proxy recording into `sender` that can be read from several threads at
+    /// once. Recording is thread-safe, fetches from all threads interleave on the sender.
+    pub fn shared(db: ExtDB, sender: UnboundedSender<NewFetch>) -> Arc<Self>
+    where
+        ExtDB: DatabaseRef + Send + Sync,
+    {
+        Arc::new(Self::new(db).with_sender(sender))
+    }
+
+    pub fn sender(&self) -> Option<&UnboundedSender<NewFetch>> {
+        self.sink.as_ref()
+    }
+
+    /// Records fetches into `sender` in addition to the current sender, if any.
+    pub fn add_sender(self, sender: UnboundedSender<NewFetch>) -> RecordingDb<ExtDB, FanOutSender> {
+        let fan_out = FanOutSender::new(self.sink.clone());
+        fan_out.push(sender);
+        self.with_sink(fan_out)
+    }
+}
+
+impl<ExtDB> RecordingProxyDB<ExtDB> {
+    pub fn new_recording(db: ExtDB) -> Self {
+        RecordingDb::new(db).with_sink(FetchBuffer::default())
+    }
+
+    /// Removes and returns every fetch recorded so far, in call order.
+    pub fn take_fetches(&self) -> Vec<NewFetch> {
+        self.sink
+            .as_ref()
+            .map(FetchBuffer::take)
+            .unwrap_or_default()
+    }
+}
+
+impl<ExtDB> RecordingDb<ExtDB, FanOutSender> {
+    /// Records fetches into `sender` as well as every sender added before.
+    pub fn add_sender(self, sender: UnboundedSender<NewFetch>) -> Self {
+        match &self.sink {
+            Some(fan_out) => {
+                fan_out.push(sender);
+                self
+            }
+            None => self.with_sink(FanOutSender::new([sender])),
+        }
+    }
+}
+
+impl<ExtDB, S> RecordingDb<ExtDB, S> {
+    /// Records fetches into `sink`, replacing any previously set sink.
+    pub fn with_sink<T: FetchSink>(self, sink: T) -> RecordingDb<ExtDB, T> {
+        RecordingDb {
+            db: self.db,
+            sink: Some(sink),
+            dedup: self.dedup,
+            skip_zero_storage: self.skip_zero_storage,
+            stats: self.stats,
+            filter: self.filter,
+            read_cache: self.read_cache,
+            record_commits: self.record_commits,
+            record_writes: self.record_writes,
+            record_cache_hits: self.record_cache_hits,
+            latency: self.latency,
+            transform: self.transform,
+            limit: self.limit,
+            block_hash_policy: self.block_hash_policy,
+            mirror: self.mirror,
+        }
+    }
+
+    /// Records fetches into `sink` behind a trait object, so the sink can later be
+    /// replaced by one of any other type with [`RecordingDb::set_dyn_sink`].
+    pub fn with_dyn_sink(
+        self,
+        sink: impl FetchSink + Send + Sync + 'static,
+    ) -> RecordingDb<ExtDB, DynSink> {
+        self.with_sink(Arc::new(sink) as DynSink)
+    }
+
+    /// Replaces the sink in place, returning the previous one. Fetches recorded from now
+    /// on go to `sink` only; clones made before keep their own sink.
+    pub fn set_sink(&mut self, sink: S) -> Option<S> {
+        self.sink.replace(sink)
+    }
+
+    /// Records fetches into `sender`, replacing any previously set sender.
+    pub fn with_sender(self, sender: UnboundedSender<NewFetch>) -> RecordingDb<ExtDB> {
+        self.with_sink(sender)
+    }
+
+    /// Records fetches into a bounded channel, applying `policy` whenever it is full.
+    pub fn with_bounded_sender(
+        self,
+        sender: Sender<NewFetch>,
+        policy: BackpressurePolicy,
+    ) -> RecordingDb<ExtDB, BoundedSender> {
+        self.with_sink(BoundedSender::new(sender, policy))
+    }
+
+    /// Records fetches into `sender` as [`TimedFetch`]es, stamped relative to `start`.
+    pub fn with_timestamps(
+        self,
+        start: Instant,
+        sender: UnboundedSender<TimedFetch>,
+    ) -> RecordingDb<ExtDB, TimedSender> {
+        self.with_sink(TimedSender::new(start, sender))
+    }
+
+    /// Records fetches into `sender` as [`SequencedFetch`]es, numbered in the order they
+    /// were emitted.
+    pub fn with_sequence_numbers(
+        self,
+        sender: UnboundedSender<SequencedFetch>,
+    ) -> RecordingDb<ExtDB, SequencedSender> {
+        self.with_sink(SequencedSender::new(sender))
+    }
+
+    /// Records fetches into `sender` in batches of `batch_size`, see [`BatchSender`].
+    pub fn with_batch_size(
+        self,
+        sender: UnboundedSender<Vec<NewFetch>>,
+        batch_size: usize,
+    ) -> RecordingDb<ExtDB, BatchSender> {
+        self.with_sink(BatchSender::new(sender, batch_size))
+    }
+
+    /// Calls `callback` inline for every fetch instead of sending it anywhere.
+    pub fn with_callback<F>(self, callback: F) -> RecordingDb<ExtDB, FetchCallback<F>>
+    where
+        F: Fn(&NewFetch),
+    {
+        self.with_sink(FetchCallback(callback))
+    }
+
+    /// Only records the first fetch of each account, storage slot and block number.
+    ///
+    /// This assumes the inner db is a stable snapshot: a slot whose value changes
+    /// between two reads (e.g. across a block boundary) is still only recorded once.
+    pub fn with_dedup(mut self) -> Self {
+        self.dedup = Some(Arc::default());
+        self
+    }
+
+    /// Treats every key already held by `cache_db` as recorded, enabling dedup if it is
+    /// not yet, so continuing a run over a previously saved cache only records new keys.
+    ///
+    /// Keys whose value changed since `cache_db` was captured are not recorded either:
+    /// [`reset`](RecordingDb::reset) the proxy instead of priming it when the state moved on.
+    pub fn prime_dedup_from<OtherDB>(mut self, cache_db: &CacheDB<OtherDB>) -> Self {
+        self.dedup.get_or_insert_with(Arc::default).prime(cache_db);
+        self
+    }
+
+    /// Like [`RecordingDb::with_dedup`], but only remembers the `capacity` most recently
+    /// seen keys of each kind, so memory stays bounded. A forgotten key is recorded
+    /// again the next time it is fetched.
+    pub fn with_dedup_capacity(mut self, capacity: usize) -> Self {
+        self.dedup = Some(Arc::new(Dedup::with_capacity(capacity)));
+        self
+    }
+
+    /// Stops recording storage reads that return zero.
+    ///
+    /// Leave this off when consumers need every touched slot, empty or not.
+    pub fn skip_zero_storage(mut self) -> Self {
+        self.skip_zero_storage = true;
+        self
+    }
+
+    /// Only records fetches for addresses accepted by `filter`. Reads for other
+    /// addresses are still served, just not recorded.
+    pub fn with_address_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&Address) -> bool + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(AddressFilter::new(filter)));
+        self
+    }
+
+    /// Rewrites each fetch right before it is recorded, dropping it when `transform`
+    /// returns `None`, e.g. to redact sensitive storage values.
+    ///
+    /// Only the recorded event changes: reads still return the true value, and the
+    /// address filter and dedup see the original fetch.
+    pub fn with_record_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&NewFetch) -> Option<NewFetch> + Send + Sync + 'static,
+    {
+        self.transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// Forgets everything learned from previous reads: the dedup set, the read cache, the
+    /// address filter's code hashes, the stats, the latency histogram and the record limit
+    /// count. The sink and configuration are kept.
+    ///
+    /// Call this at block or simulation boundaries when reusing a proxy over a db whose
+    /// state changed in between, otherwise stale entries are served and new values of
+    /// keys seen before are not recorded. Clones share this state and are reset as well.
+    pub fn reset(&self) {
+        if let Some(dedup) = &self.dedup {
+            dedup.clear();
+        }
+        if let Some(read_cache) = &self.read_cache {
+            read_cache.clear();
+        }
+        if let Some(filter) = &self.filter {
+            filter.clear();
+        }
+        self.stats.reset();
+        if let Some(latency) = &self.latency {
+            latency.reset();
+        }
+        if let Some(limit) = &self.limit {
+            limit.reset();
+        }
+    }
+
+    /// Stops recording once `limit` events have been recorded, e.g. to sample the first
+    /// accesses of a long run. Reads keep being served as usual, see
+    /// [`RecordingDb::record_complete`].
+    pub fn with_record_limit(mut self, limit: u64) -> Self {
+        self.limit = Some(Arc::new(RecordLimit::new(limit)));
+        self
+    }
+
+    /// Whether the limit set with [`RecordingDb::with_record_limit`] has been reached.
+    pub fn record_complete(&self) -> bool {
+        self.limit.as_ref().is_some_and(|limit| limit.is_reached())
+    }
+
+    /// Serves repeated lookups from memory instead of the inner db.
+    ///
+    /// Only the first, real fetch of each key is recorded and counted in the stats.
+    pub fn with_read_cache(mut self) -> Self {
+        self.read_cache = Some(Arc::default());
+        self
+    }
+
+    /// Like [`RecordingDb::with_read_cache`], but only keeps the `capacity` most recently
+    /// used entries of each kind. An evicted entry is fetched from the inner db again,
+    /// and recorded again unless dedup still remembers it.
+    pub fn with_read_cache_capacity(mut self, capacity: usize) -> Self {
+        self.read_cache = Some(Arc::new(ReadCache::with_capacity(capacity)));
+        self
+    }
+
+    /// Also records a [`NewFetch::CacheHit`] for every lookup the read cache answers, so
+    /// each access can be attributed to the cache or, through its regular fetch, to the
+    /// inner db. Hits bypass the dedup set.
+    pub fn record_cache_hits(mut self) -> Self {
+        self.record_cache_hits = true;
+        self
+    }
+
+    /// Sets what is recorded when the inner db returns a zero block hash. The caller still
+    /// gets the zero hash back under every policy.
+    pub fn with_block_hash_policy(mut self, policy: BlockHashPolicy) -> Self {
+        self.block_hash_policy = policy;
+        self
+    }
+
+    /// Also records the accounts and storage slots written by [`DatabaseCommit::commit`],
+    /// as `Basic` and `Storage` fetches holding their new values, so a cache replayed from
+    /// the fetches ends up in the committed state. Commits bypass the dedup set.
+    pub fn record_commits(mut self) -> Self {
+        self.record_commits = true;
+        self
+    }
+
+    /// Also records what [`DatabaseCommit::commit`] writes, as [`NewFetch::BasicWrite`] and
+    /// [`NewFetch::StorageWrite`] events kept apart from the reads, so both the pre-state
+    /// and the post-state can be rebuilt, see [`post_state_from_fetches`]. Writes bypass
+    /// the dedup set.
+    pub fn record_writes(mut self) -> Self {
+        self.record_writes = true;
+        self
+    }
+
+    /// Also counts distinct keys per lookup kind in [`RecordingDb::stats`], at the cost of
+    /// keeping every key seen in memory.
+    pub fn with_unique_stats(mut self) -> Self {
+        self.stats = Arc::new(Stats::with_unique());
+        self
+    }
+
+    /// Lookups forwarded to the inner db so far.
+    pub fn stats(&self) -> DbStats {
+        self.stats.snapshot()
+    }
+
+    /// Times every call forwarded to the inner db, see [`RecordingDb::latency_histogram`].
+    /// Calls answered by the read cache are not measured.
+    pub fn with_latency_histogram(mut self) -> Self {
+        self.latency = Some(Arc::default());
+        self
+    }
+
+    /// How long the inner db took to answer, if enabled with
+    /// [`RecordingDb::with_latency_histogram`]. Failed calls are included.
+    pub fn latency_histogram(&self) -> Option<LatencyHistogram> {
+        self.latency.as_deref().map(Latency::snapshot)
+    }
+
+    pub fn inner(&self) -> &ExtDB {
+        &self.db
+    }
+
+    /// The inner db, e.g. to reconfigure it mid-run. Values already recorded or held
+    /// by the read cache are not refreshed.
+    pub fn inner_mut(&mut self) -> &mut ExtDB {
+        &mut self.db
+    }
+
+    pub fn into_inner(self) -> ExtDB {
+        self.db
+    }
+
+    pub fn sink(&self) -> Option<&S> {
+        self.sink.as_ref()
+    }
+
+    /// Also inserts every value read from the inner db into an offline cache as it is
+    /// read, regardless of the sink, filter and dedup set. Without a sink this replaces
+    /// recording altogether, see [`RecordingDb::into_cache`].
+    pub fn with_mirror_cache(self) -> Self {
+        self.with_shared_mirror_cache(Arc::default())
+    }
+
+    /// Like [`RecordingDb::with_mirror_cache`], mirroring into `cache_db`, e.g. to fill
+    /// one cache from several proxies.
+    pub fn with_shared_mirror_cache(mut self, cache_db: Arc<Mutex<CacheDB<EmptyDB>>>) -> Self {
+        self.mirror = Some(cache_db);
+        self
+    }
+
+    pub fn mirror_cache(&self) -> Option<&Arc<Mutex<CacheDB<EmptyDB>>>> {
+        self.mirror.as_ref()
+    }
+
+    /// The cache mirrored so far, cloned if another clone of this proxy still shares it.
+    /// `None` unless enabled with [`RecordingDb::with_mirror_cache`].
+    pub fn into_cache(self) -> Option<CacheDB<EmptyDB>> {
+        let mirror = self.mirror?;
+        let mirror = Arc::try_unwrap(mirror).unwrap_or_else(|mirror| {
+            let cache_db = mirror.lock().unwrap_or_else(|err| err.into_inner());
+            Mutex::new(cache_db.clone())
+        });
+        Some(mirror.into_inner().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    fn mirror(&self, op: impl FnOnce() -> CacheOp) {
+        if let Some(mirror) = &self.mirror {
+            if let Ok(mut cache_db) = mirror.lock() {
+                // Code not matching its hash is left out, as when replaying it.
+                let _ = op().apply(&mut cache_db);
+            }
+        }
+    }
+
+    /// Drops the sink, closing a channel once no clone of this proxy holds it anymore.
+    ///
+    /// Reads keep being served afterwards but are no longer recorded. See
+    /// [`drain_receiver`] for collecting what was sent before.
+    pub fn flush(&mut self) {
+        self.sink = None;
+    }
+}
+
+impl<ExtDB, S> RecordingDb<ExtDB, S>
 where
-    ExtDB: DatabaseRef,
+    S: FetchSink,
 {
-    pub fn new(db: ExtDB) -> Self {
-        Self { db, sender: None }
+    /// Records a [`NewFetch::Summary`] of [`RecordingDb::stats`] as the last event, then
+    /// drops the sink as [`RecordingDb::flush`] does.
+    ///
+    /// The summary goes straight to the sink, bypassing the filter, transform and record
+    /// limit, so a consumer can rely on it to detect the end of a capture.
+    pub fn finish(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.record(NewFetch::Summary {
+                stats: self.stats(),
+            });
+        }
+    }
+
+    /// Whether fetches are still being delivered, i.e. a sink is set and has not been
+    /// closed. Check this after long captures: once the receiver of a channel sink is
+    /// dropped, every later fetch is silently lost.
+    pub fn sink_healthy(&self) -> bool {
+        self.sink.as_ref().is_some_and(|sink| !sink.is_closed())
+    }
+
+    fn record(&self, fetch: NewFetch) {
+        self.emit(fetch, true);
+    }
+
+    fn emit(&self, fetch: NewFetch, dedup: bool) {
+        let Some(sink) = self.sink.as_ref().filter(|sink| !sink.is_closed()) else {
+            return;
+        };
+        if let Some(filter) = &self.filter {
+            if !filter.allows(&fetch) {
+                return;
+            }
+        }
+        if let Some(dedup) = self.dedup.as_ref().filter(|_| dedup) {
+            if !dedup.first_seen(&fetch) {
+                return;
+            }
+        }
+        let fetch = match &self.transform {
+            Some(transform) => match transform(&fetch) {
+                Some(fetch) => fetch,
+                None => return,
+            },
+            None => fetch,
+        };
+        if let Some(limit) = &self.limit {
+            if !limit.try_acquire() {
+                return;
+            }
+        }
+        sink.record(fetch);
+    }
+
+    /// Records the touched accounts and changed slots of `changes`, as writes or as
+    /// regular fetches.
+    fn record_commit(&self, changes: &HashMap<Address, Account>, as_writes: bool) {
+        if !self.sink_healthy() {
+            return;
+        }
+        for (address, account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+            let (address, account_info) = (*address, account.info.clone());
+            self.emit(
+                if as_writes {
+                    NewFetch::BasicWrite {
+                        address,
+                        account_info,
+                    }
+                } else {
+                    NewFetch::Basic {
+                        address,
+                        account_info,
+                    }
+                },
+                false,
+            );
+            for (index, slot) in account.changed_storage_slots() {
+                let (index, value) = (*index, slot.present_value);
+                self.emit(
+                    if as_writes {
+                        NewFetch::StorageWrite {
+                            address,
+                            index,
+                            value,
+                        }
+                    } else {
+                        NewFetch::Storage {
+                            address,
+                            index,
+                            value,
+                        }
+                    },
+                    false,
+                );
+            }
+        }
+    }
+
+    /// Looks `key` up in the read cache, if enabled.
+    fn cached<T>(&self, key: FetchKey, lookup: impl FnOnce(&ReadCache) -> Option<T>) -> Option<T> {
+        let value = lookup(self.read_cache.as_deref()?)?;
+        if self.record_cache_hits {
+            self.emit(NewFetch::CacheHit { key }, false);
+        }
+        Some(value)
+    }
+
+    fn record_error(
+        &self,
+        kind: FetchKind,
+        address: Option<Address>,
+        index: Option<U256>,
+        err: &impl fmt::Display,
+    ) {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "revm_proxy_db", ?kind, ?address, ?index, %err, "error");
+        if !self.sink_healthy() {
+            return;
+        }
+        self.record(NewFetch::Error {
+            kind,
+            address,
+            index,
+            message: err.to_string(),
+        });
+    }
+
+    fn record_basic(&self, address: Address, account_info: &Option<AccountInfo>) {
+        self.stats.basic(address);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "revm_proxy_db", %address, found = account_info.is_some(), "basic");
+        if let Some(read_cache) = &self.read_cache {
+            read_cache.insert_basic(address, account_info.clone());
+        }
+        self.mirror(|| match account_info {
+            Some(info) => CacheOp::InsertAccount {
+                address,
+                info: info.clone(),
+            },
+            None => CacheOp::InsertMissingAccount { address },
+        });
+        // Skip building the event, and cloning the info into it, when nothing records it.
+        if !self.sink_healthy() {
+            return;
+        }
+        match account_info {
+            Some(account_info) => self.record(NewFetch::Basic {
+                address,
+                account_info: account_info.clone(),
+            }),
+            None => self.record(NewFetch::BasicMissing { address }),
+        }
+    }
+
+    fn record_code_by_hash(&self, code_hash: B256, bytecode: &Bytecode) {
+        self.stats.code(code_hash);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "revm_proxy_db", %code_hash, len = bytecode.len(), "code_by_hash");
+        if let Some(read_cache) = &self.read_cache {
+            read_cache.insert_code_by_hash(code_hash, bytecode.clone());
+        }
+        self.mirror(|| CacheOp::InsertCode {
+            code_hash,
+            bytecode: bytecode.clone(),
+        });
+        if !self.sink_healthy() {
+            return;
+        }
+        self.record(NewFetch::CodeByHash {
+            code_hash,
+            bytecode: bytecode.clone(),
+        });
+    }
+
+    fn record_storage(&self, address: Address, index: U256, value: U256) {
+        self.stats.storage(address, index);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "revm_proxy_db", %address, %index, %value, "storage");
+        if let Some(read_cache) = &self.read_cache {
+            read_cache.insert_storage(address, index, value);
+        }
+        self.mirror(|| CacheOp::InsertStorage {
+            address,
+            index,
+            value,
+        });
+        if !self.sink_healthy() || (self.skip_zero_storage && value.is_zero()) {
+            return;
+        }
+        self.record(NewFetch::Storage {
+            address,
+            index,
+            value,
+        });
+    }
+
+    fn record_block_hash(&self, number: u64, hash: B256) {
+        self.stats.block_hash(number);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "revm_proxy_db", number, %hash, found = !hash.is_zero(), "block_hash");
+        if let Some(read_cache) = &self.read_cache {
+            read_cache.insert_block_hash(number, hash);
+        }
+        self.mirror(|| CacheOp::InsertBlockHash { number, hash });
+        if !self.sink_healthy() {
+            return;
+        }
+        if hash.is_zero() {
+            match self.block_hash_policy {
+                BlockHashPolicy::Record => {}
+                BlockHashPolicy::Skip => return,
+                BlockHashPolicy::Error => {
+                    let err = format!("no hash for block {number}");
+                    self.record_error(FetchKind::BlockHash, None, None, &err);
+                    return;
+                }
+            }
+        }
+        self.record(NewFetch::BlockHash { number, hash });
     }
 }
 
-impl<ExtDB> DatabaseRef for ProxyDB<ExtDB>
+impl<ExtDB> RecordingDb<ExtDB, BatchSender> {
+    /// Sends the fetches recorded since the last full batch, without closing the channel.
+    pub fn flush_batch(&self) {
+        if let Some(sink) = &self.sink {
+            sink.flush();
+        }
+    }
+}
+
+impl<ExtDB> RecordingDb<ExtDB, BoundedSender> {
+    /// Whether the bounded sender has rejected a fetch, see [`BoundedSender::overflowed`].
+    pub fn overflowed(&self) -> bool {
+        self.sink.as_ref().is_some_and(BoundedSender::overflowed)
+    }
+}
+
+impl<ExtDB> RecordingDb<ExtDB, DynSink> {
+    /// Like [`RecordingDb::set_sink`], boxing `sink` so it can be of any type.
+    pub fn set_dyn_sink(
+        &mut self,
+        sink: impl FetchSink + Send + Sync + 'static,
+    ) -> Option<DynSink> {
+        self.set_sink(Arc::new(sink))
+    }
+}
+
+impl<ExtDB, S> DatabaseRef for RecordingDb<ExtDB, S>
 where
     ExtDB: DatabaseRef,
+    ExtDB::Error: fmt::Display,
+    S: FetchSink,
 {
     #[doc = " The database error type."]
     type Error = ExtDB::Error;
 
     #[doc = " Get basic account information."]
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
-        let account_info = self.db.basic_ref(address)?;
-        if let Some(account_info) = &account_info {
-            self.sender.as_ref().inspect(|sender| {
-                let _ = sender.send(NewFetch::Basic {
-                    address,
-                    account_info: account_info.clone(),
-                });
-            });
+        if let Some(account_info) = self.cached(FetchKey::Basic(address), |c| c.basic(address)) {
+            return Ok(account_info);
         }
+        let account_info = latency::time(self.latency.as_deref(), || self.db.basic_ref(address))
+            .inspect_err(|err| self.record_error(FetchKind::Basic, Some(address), None, err))?;
+        self.record_basic(address, &account_info);
         Ok(account_info)
     }
 
     #[doc = " Get account code by its hash."]
     fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
-        self.db.code_by_hash_ref(code_hash)
+        if let Some(bytecode) = self.cached(FetchKey::CodeByHash(code_hash), |c| {
+            c.code_by_hash(code_hash)
+        }) {
+            return Ok(bytecode);
+        }
+        let bytecode = latency::time(self.latency.as_deref(), || {
+            self.db.code_by_hash_ref(code_hash)
+        })
+        .inspect_err(|err| self.record_error(FetchKind::CodeByHash, None, None, err))?;
+        self.record_code_by_hash(code_hash, &bytecode);
+        Ok(bytecode)
     }
 
     #[doc = " Get storage value of address at index."]
     fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
-        let value = self.db.storage_ref(address, index)?;
-        self.sender.as_ref().inspect(|sender| {
-            let _ = sender.send(NewFetch::Storage {
-                address,
-                index,
-                value,
-            });
-        });
+        if let Some(value) = self.cached(FetchKey::Storage(address, index), |c| {
+            c.storage(address, index)
+        }) {
+            return Ok(value);
+        }
+        let value = latency::time(self.latency.as_deref(), || {
+            self.db.storage_ref(address, index)
+        })
+        .inspect_err(|err| {
+            self.record_error(FetchKind::Storage, Some(address), Some(index), err)
+        })?;
+        self.record_storage(address, index, value);
         Ok(value)
     }
 
     #[doc = " Get block hash by block number."]
     fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
-        self.db.block_hash_ref(number)
-    }
-}
-
-pub fn save_cache_db_to_file<ExtDB>(path: String, cache_db: &CacheDB<ExtDB>) -> eyre::Result<()> {
-    let CacheDB {
-        accounts,
-        contracts,
-        ..
-    } = cache_db;
-    let db = CacheDB {
-        accounts: accounts.clone(),
-        contracts: contracts.clone(),
-        logs: Default::default(),
-        block_hashes: Default::default(),
-        db: EmptyDB::new(),
-    };
-    let json = serde_json::to_string(&db)?;
-    let mut file = File::create(path)?;
-    file.write_all(json.as_bytes())?;
-    Ok(())
-}
-
-pub fn load_cache_db_from_file<ExtDB>(path: String) -> eyre::Result<CacheDB<ExtDB>>
+        if let Some(hash) = self.cached(FetchKey::BlockHash(number), |c| c.block_hash(number)) {
+            return Ok(hash);
+        }
+        let hash = latency::time(self.latency.as_deref(), || self.db.block_hash_ref(number))
+            .inspect_err(|err| self.record_error(FetchKind::BlockHash, None, None, err))?;
+        self.record_block_hash(number, hash);
+        Ok(hash)
+    }
+}
+
+impl<ExtDB, S> Database for RecordingDb<ExtDB, S>
+where
+    ExtDB: Database,
+    ExtDB::Error: fmt::Display,
+    S: FetchSink,
+{
+    #[doc = " The database error type."]
+    type Error = ExtDB::Error;
+
+    #[doc = " Get basic account information."]
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        if let Some(account_info) = self.cached(FetchKey::Basic(address), |c| c.basic(address)) {
+            return Ok(account_info);
+        }
+        let account_info = latency::time(self.latency.as_deref(), || self.db.basic(address))
+            .inspect_err(|err| self.record_error(FetchKind::Basic, Some(address), None, err))?;
+        self.record_basic(address, &account_info);
+        Ok(account_info)
+    }
+
+    #[doc = " Get account code by its hash."]
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if let Some(bytecode) = self.cached(FetchKey::CodeByHash(code_hash), |c| {
+            c.code_by_hash(code_hash)
+        }) {
+            return Ok(bytecode);
+        }
+        let bytecode =
+            latency::time(self.latency.as_deref(), || self.db.code_by_hash(code_hash))
+                .inspect_err(|err| self.record_error(FetchKind::CodeByHash, None, None, err))?;
+        self.record_code_by_hash(code_hash, &bytecode);
+        Ok(bytecode)
+    }
+
+    #[doc = " Get storage value of address at index."]
+    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        if let Some(value) = self.cached(FetchKey::Storage(address, index), |c| {
+            c.storage(address, index)
+        }) {
+            return Ok(value);
+        }
+        let value = latency::time(self.latency.as_deref(), || self.db.storage(address, index))
+            .inspect_err(|err| {
+                self.record_error(FetchKind::Storage, Some(address), Some(index), err)
+            })?;
+        self.record_storage(address, index, value);
+        Ok(value)
+    }
+
+    #[doc = " Get block hash by block number."]
+    fn block_hash(&mut self, number: u64) -> Result<B256, Self::Error> {
+        if let Some(hash) = self.cached(FetchKey::BlockHash(number), |c| c.block_hash(number)) {
+            return Ok(hash);
+        }
+        let hash = latency::time(self.latency.as_deref(), || self.db.block_hash(number))
+            .inspect_err(|err| self.record_error(FetchKind::BlockHash, None, None, err))?;
+        self.record_block_hash(number, hash);
+        Ok(hash)
+    }
+}
+
+impl<ExtDB, S> DatabaseCommit for RecordingDb<ExtDB, S>
 where
-    ExtDB: DeserializeOwned,
+    ExtDB: DatabaseCommit,
+    S: FetchSink,
 {
-    let mut file = File::open(path)?;
-    let mut json = String::new();
-    let _ = file.read_to_string(&mut json)?;
-    let cache_db = serde_json::from_str::<CacheDB<ExtDB>>(&json)?;
-    Ok(cache_db)
+    fn commit(&mut self, changes: HashMap<Address, Account>) {
+        if let Some(read_cache) = &self.read_cache {
+            for address in changes.keys() {
+                read_cache.invalidate_account(*address);
+            }
+        }
+        if self.record_commits {
+            self.record_commit(&changes, false);
+        }
+        if self.record_writes {
+            self.record_commit(&changes, true);
+        }
+        self.db.commit(changes)
+    }
 }
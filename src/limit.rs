@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Caps the number of events a [`RecordingDb`](crate::RecordingDb) emits.
+#[derive(Debug)]
+pub(crate) struct RecordLimit {
+    max: u64,
+    emitted: AtomicU64,
+}
+
+impl RecordLimit {
+    pub(crate) fn new(max: u64) -> Self {
+        Self {
+            max,
+            emitted: AtomicU64::new(0),
+        }
+    }
+
+    /// Claims a slot for one more event, returning `false` once the limit is reached.
+    pub(crate) fn try_acquire(&self) -> bool {
+        self.emitted
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |emitted| {
+                (emitted < self.max).then_some(emitted + 1)
+            })
+            .is_ok()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.emitted.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_reached(&self) -> bool {
+        self.emitted.load(Ordering::Relaxed) >= self.max
+    }
+}
@@ -0,0 +1,375 @@
+#[cfg(feature = "persistence")]
+use std::path::Path;
+use std::{
+    convert::Infallible,
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::Duration,
+};
+
+#[cfg(feature = "persistence")]
+use revm::db::{CacheDB, EmptyDB};
+use revm::{
+    primitives::{AccountInfo, Address, Bytecode, HashMap, B256, U256},
+    DatabaseRef,
+};
+
+#[cfg(feature = "persistence")]
+use crate::{
+    apply_fetches_to_cache_db, load_cache_db_from_file, save_cache_db_to_file, verify_replay,
+    ProxyDbError, RecordingDb, RecordingProxyDB, ReplayMismatch,
+};
+use crate::{stats::Stats, AsyncDatabaseRef, DbStats};
+
+/// An in-memory [`DatabaseRef`] for tests, counting every call made to it.
+///
+/// Missing accounts read as `None` and missing storage, code and block hashes as their
+/// zero or empty value.
+#[derive(Debug, Default)]
+pub struct MockDb {
+    accounts: HashMap<Address, AccountInfo>,
+    storage: HashMap<(Address, U256), U256>,
+    contracts: HashMap<B256, Bytecode>,
+    block_hashes: HashMap<u64, B256>,
+    calls: Stats,
+    delay: Duration,
+}
+
+impl MockDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_account(mut self, address: Address, account_info: AccountInfo) -> Self {
+        self.accounts.insert(address, account_info);
+        self
+    }
+
+    pub fn with_storage(mut self, address: Address, index: U256, value: U256) -> Self {
+        self.storage.insert((address, index), value);
+        self
+    }
+
+    /// Serves `bytecode` under its hash, without attaching it to any account.
+    pub fn with_code(mut self, bytecode: Bytecode) -> Self {
+        self.contracts.insert(bytecode.hash_slow(), bytecode);
+        self
+    }
+
+    pub fn with_block_hash(mut self, number: u64, hash: B256) -> Self {
+        self.block_hashes.insert(number, hash);
+        self
+    }
+
+    /// Sleeps for `delay` on every call, to stand in for a slow backend. The async calls
+    /// sleep without blocking the runtime.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn wait(&self) {
+        if !self.delay.is_zero() {
+            thread::sleep(self.delay);
+        }
+    }
+
+    async fn wait_async(&self) {
+        if !self.delay.is_zero() {
+            tokio::time::sleep(self.delay).await;
+        }
+    }
+
+    /// Number of calls made to each method so far.
+    pub fn calls(&self) -> DbStats {
+        self.calls.snapshot()
+    }
+}
+
+impl DatabaseRef for MockDb {
+    type Error = Infallible;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.calls.basic(address);
+        self.wait();
+        Ok(self.accounts.get(&address).cloned())
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.calls.code(code_hash);
+        self.wait();
+        Ok(self.contracts.get(&code_hash).cloned().unwrap_or_default())
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.calls.storage(address, index);
+        self.wait();
+        Ok(self
+            .storage
+            .get(&(address, index))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.calls.block_hash(number);
+        self.wait();
+        Ok(self.block_hashes.get(&number).copied().unwrap_or_default())
+    }
+}
+
+impl AsyncDatabaseRef for MockDb {
+    type Error = Infallible;
+
+    async fn basic_async_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.calls.basic(address);
+        self.wait_async().await;
+        Ok(self.accounts.get(&address).cloned())
+    }
+
+    async fn code_by_hash_async_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.calls.code(code_hash);
+        self.wait_async().await;
+        Ok(self.contracts.get(&code_hash).cloned().unwrap_or_default())
+    }
+
+    async fn storage_async_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.calls.storage(address, index);
+        self.wait_async().await;
+        Ok(self
+            .storage
+            .get(&(address, index))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    async fn block_hash_async_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.calls.block_hash(number);
+        self.wait_async().await;
+        Ok(self.block_hashes.get(&number).copied().unwrap_or_default())
+    }
+}
+
+/// The error a [`FlakyDb`] fails its first lookups with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unavailable {
+    /// Number of calls made before the failed one.
+    pub attempt: u32,
+}
+
+impl fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("backend unavailable")
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+/// A [`MockDb`] whose first `failures` calls fail with [`Unavailable`], for testing error
+/// and retry paths. Later calls are served by the mock.
+#[derive(Debug)]
+pub struct FlakyDb {
+    db: MockDb,
+    failures: u32,
+    attempts: AtomicU32,
+}
+
+impl FlakyDb {
+    pub fn new(db: MockDb, failures: u32) -> Self {
+        Self {
+            db,
+            failures,
+            attempts: AtomicU32::new(0),
+        }
+    }
+
+    /// Fails the first `failures` calls, then serves an empty state.
+    pub fn failing(failures: u32) -> Self {
+        Self::new(MockDb::new(), failures)
+    }
+
+    /// Number of calls made so far, failed or not.
+    pub fn attempts(&self) -> u32 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    pub fn inner(&self) -> &MockDb {
+        &self.db
+    }
+
+    fn attempt(&self) -> Result<(), Unavailable> {
+        let attempt = self.attempts.fetch_add(1, Ordering::Relaxed);
+        if attempt < self.failures {
+            return Err(Unavailable { attempt });
+        }
+        Ok(())
+    }
+}
+
+impl DatabaseRef for FlakyDb {
+    type Error = Unavailable;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.attempt()?;
+        self.db.basic_ref(address).map_err(|never| match never {})
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.attempt()?;
+        self.db
+            .code_by_hash_ref(code_hash)
+            .map_err(|never| match never {})
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.attempt()?;
+        self.db
+            .storage_ref(address, index)
+            .map_err(|never| match never {})
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.attempt()?;
+        self.db
+            .block_hash_ref(number)
+            .map_err(|never| match never {})
+    }
+}
+
+impl AsyncDatabaseRef for FlakyDb {
+    type Error = Unavailable;
+
+    async fn basic_async_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.attempt()?;
+        self.db
+            .basic_async_ref(address)
+            .await
+            .map_err(|never| match never {})
+    }
+
+    async fn code_by_hash_async_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.attempt()?;
+        self.db
+            .code_by_hash_async_ref(code_hash)
+            .await
+            .map_err(|never| match never {})
+    }
+
+    async fn storage_async_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.attempt()?;
+        self.db
+            .storage_async_ref(address, index)
+            .await
+            .map_err(|never| match never {})
+    }
+
+    async fn block_hash_async_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.attempt()?;
+        self.db
+            .block_hash_async_ref(number)
+            .await
+            .map_err(|never| match never {})
+    }
+}
+
+/// Runs the whole record and replay pipeline over `live`, for tests.
+///
+/// Records the lookups `reads` makes through a proxy over `live` in memory, applies the
+/// fetches to a fresh cache, saves that cache to `path` and loads it
+/// back, then reads every recorded key from both the reloaded cache and `live`, see
+/// [`verify_replay`]. Returns the keys served differently, empty if the round trip is
+/// lossless. Reads may come in any order, e.g. storage before its account.
+#[cfg(feature = "persistence")]
+pub fn record_and_replay(
+    live: MockDb,
+    path: impl AsRef<Path>,
+    reads: impl FnOnce(&RecordingProxyDB<MockDb>),
+) -> Result<Vec<ReplayMismatch>, ProxyDbError> {
+    let db = RecordingDb::new_recording(live);
+    reads(&db);
+    let fetches = db.take_fetches();
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    apply_fetches_to_cache_db(&mut cache_db, fetches.iter().cloned())?;
+    save_cache_db_to_file(path.as_ref(), &cache_db)?;
+    let replayed = load_cache_db_from_file(path.as_ref())?;
+    verify_replay(&fetches, &replayed, db.inner()).map_err(|never| match never {})
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use revm::primitives::Bytes;
+
+    use super::*;
+    use crate::{drain_receiver, NewFetch, RecordingDb};
+
+    fn seeded() -> (MockDb, Address, B256) {
+        let address = Address::with_last_byte(1);
+        let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+        let code_hash = bytecode.hash_slow();
+        let mock = MockDb::new()
+            .with_account(address, AccountInfo::default())
+            .with_storage(address, U256::from(1), U256::from(2))
+            .with_code(bytecode)
+            .with_block_hash(1, B256::repeat_byte(1));
+        (mock, address, code_hash)
+    }
+
+    fn once() -> DbStats {
+        DbStats {
+            basic_reads: 1,
+            storage_reads: 1,
+            code_reads: 1,
+            block_hash_reads: 1,
+            unique: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn read_cache_and_dedup_reach_the_inner_db_once_per_key() {
+        let (mock, address, code_hash) = seeded();
+        let (db, receiver) = RecordingDb::new_with_channel(mock);
+        let db = db.with_dedup().with_read_cache();
+        for _ in 0..3 {
+            db.basic_ref(address).unwrap();
+            db.storage_ref(address, U256::from(1)).unwrap();
+            db.code_by_hash_ref(code_hash).unwrap();
+            db.block_hash_ref(1).unwrap();
+        }
+
+        assert_eq!(db.inner().calls(), once());
+        drop(db);
+        let fetches = drain_receiver(receiver).await;
+        assert_eq!(fetches.len(), 4);
+        assert!(fetches.contains(&NewFetch::Storage {
+            address,
+            index: U256::from(1),
+            value: U256::from(2),
+        }));
+    }
+
+    #[tokio::test]
+    async fn async_calls_are_counted_like_sync_ones() {
+        let (mock, address, code_hash) = seeded();
+        mock.basic_async_ref(address).await.unwrap();
+        mock.storage_async_ref(address, U256::from(1))
+            .await
+            .unwrap();
+        mock.code_by_hash_async_ref(code_hash).await.unwrap();
+        mock.block_hash_async_ref(1).await.unwrap();
+        assert_eq!(mock.calls(), once());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn async_calls_are_counted_before_the_delay() {
+        let mock = std::sync::Arc::new(MockDb::new().with_delay(Duration::from_secs(1)));
+        let call = tokio::spawn({
+            let mock = mock.clone();
+            async move { mock.basic_async_ref(Address::ZERO).await }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(mock.calls().basic_reads, 1);
+        assert!(!call.is_finished());
+        assert_eq!(call.await.unwrap().unwrap(), None);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    time::Duration,
+};
+
+use flate2::{write::GzEncoder, Compression};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::mpsc::UnboundedReceiver,
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+use crate::{persistence::MaybeGzip, NewFetch, ProxyDbError};
+
+/// Spawns a task that appends every fetch received on `receiver` to `writer` as one JSON
+/// object per line.
+///
+/// The writer is flushed whenever the channel runs dry, so a crash loses at most the
+/// burst in flight. The task ends once every sender is dropped, handing the writer back.
+pub fn spawn_ndjson_writer<W>(
+    mut receiver: UnboundedReceiver<NewFetch>,
+    writer: W,
+) -> JoinHandle<Result<W, ProxyDbError>>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut writer = BufWriter::new(writer);
+        while let Some(fetch) = receiver.recv().await {
+            let mut line = serde_json::to_vec(&fetch)?;
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+            if receiver.is_empty() {
+                writer.flush().await?;
+            }
+        }
+        writer.flush().await?;
+        Ok(writer.into_inner())
+    })
+}
+
+/// Like [`spawn_ndjson_writer`], but flushes every `flush_interval` instead of whenever
+/// the channel runs dry, bounding both the lines a crash can lose and the number of
+/// flushes under steady traffic. Lines spill to `writer` in between once its buffer
+/// fills up. Intervals without new lines do not flush.
+///
+/// Whatever is left is flushed when the channel closes. A zero interval is treated as
+/// one millisecond.
+pub fn spawn_ndjson_writer_with_interval<W>(
+    mut receiver: UnboundedReceiver<NewFetch>,
+    writer: W,
+    flush_interval: Duration,
+) -> JoinHandle<Result<W, ProxyDbError>>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut writer = BufWriter::new(writer);
+        let mut interval = tokio::time::interval(flush_interval.max(Duration::from_millis(1)));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut dirty = false;
+        loop {
+            tokio::select! {
+                fetch = receiver.recv() => {
+                    let Some(fetch) = fetch else {
+                        break;
+                    };
+                    let mut line = serde_json::to_vec(&fetch)?;
+                    line.push(b'\n');
+                    writer.write_all(&line).await?;
+                    dirty = true;
+                }
+                _ = interval.tick() => {
+                    if dirty {
+                        writer.flush().await?;
+                        dirty = false;
+                    }
+                }
+            }
+        }
+        writer.flush().await?;
+        Ok(writer.into_inner())
+    })
+}
+
+/// Gzipped counterpart of [`spawn_ndjson_writer`].
+///
+/// Every burst written between two flushes is a complete gzip member of its own, so a
+/// crash only loses the burst in flight and the file stays readable by
+/// [`load_fetches_from_ndjson`] and `gzip -d`. Sparse traffic compresses worse, as each
+/// member carries its own header and dictionary.
+pub fn spawn_ndjson_writer_compressed<W>(
+    mut receiver: UnboundedReceiver<NewFetch>,
+    mut writer: W,
+) -> JoinHandle<Result<W, ProxyDbError>>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        while let Some(fetch) = receiver.recv().await {
+            serde_json::to_writer(&mut encoder, &fetch)?;
+            encoder.write_all(b"\n")?;
+            if receiver.is_empty() {
+                let member = std::mem::replace(
+                    &mut encoder,
+                    GzEncoder::new(Vec::new(), Compression::default()),
+                )
+                .finish()?;
+                writer.write_all(&member).await?;
+                writer.flush().await?;
+            }
+        }
+        writer.flush().await?;
+        Ok(writer)
+    })
+}
+
+/// Reads back fetches written by [`spawn_ndjson_writer`] or
+/// [`spawn_ndjson_writer_compressed`], gunzipping the input first if it is compressed, and
+/// skipping blank lines.
+///
+/// Iteration stops at the first line that fails to read or parse, after yielding its
+/// error, so a capture truncated by a crash yields every complete line before it.
+pub fn load_fetches_from_ndjson<R: Read>(
+    reader: R,
+) -> impl Iterator<Item = Result<NewFetch, ProxyDbError>> {
+    let mut lines = match MaybeGzip::new(reader) {
+        Ok(reader) => Ok(BufReader::new(reader).lines()),
+        Err(err) => Err(Some(err)),
+    };
+    let mut failed = false;
+    std::iter::from_fn(move || loop {
+        if failed {
+            return None;
+        }
+        let lines = match &mut lines {
+            Ok(lines) => lines,
+            Err(err) => {
+                failed = true;
+                return err.take().map(|err| Err(err.into()));
+            }
+        };
+        let line = match lines.next()? {
+            Ok(line) => line,
+            Err(err) => {
+                failed = true;
+                return Some(Err(err.into()));
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fetch = serde_json::from_str(&line).map_err(ProxyDbError::from);
+        failed = fetch.is_err();
+        return Some(fetch);
+    })
+}
@@ -0,0 +1,1023 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, PoisonError, Weak},
+};
+
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder, Compression};
+use revm::{
+    db::{AccountState, CacheDB, DbAccount, EmptyDB},
+    primitives::{AccountInfo, Address, Bytecode, Log, B256, U256},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    apply_fetches_to_cache_db, cache_db_delta, cache_db_subset, diff_cache_dbs, flatten_cache_db,
+    merge_cache_dbs, CacheLayers, NewFetch, ProxyDbError,
+};
+
+/// Prefix of every cache written by this crate, followed by a little-endian `u16` version.
+const HEADER_MAGIC: [u8; 4] = *b"RPDB";
+
+/// Version of the cache layout written by the save helpers.
+///
+/// Bump this whenever the serialized `CacheDB` layout changes, and teach
+/// [`check_version`] how to read the previous versions.
+pub const CACHE_FORMAT_VERSION: u16 = 1;
+
+/// The gzip magic bytes followed by the deflate compression method.
+const GZIP_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+
+/// A reader that transparently gunzips its input if it starts with the gzip magic bytes.
+///
+/// Concatenated gzip members are decoded one after the other, as `gzip -d` does.
+pub(crate) enum MaybeGzip<R> {
+    Plain(BufReader<R>),
+    Gzip(MultiGzDecoder<BufReader<R>>),
+}
+
+impl<R: Read> MaybeGzip<R> {
+    pub(crate) fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        // Uncompressed caches start with the `RPDB` header. Headerless legacy caches start
+        // with `{` (JSON) or their account count as a little-endian u64 (bincode), which
+        // only collides when that count is 0x088b1f mod 2^24.
+        if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Ok(Self::Gzip(MultiGzDecoder::new(reader)))
+        } else {
+            Ok(Self::Plain(reader))
+        }
+    }
+}
+
+impl<R: Read> Read for MaybeGzip<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(reader) => reader.read(buf),
+            Self::Gzip(reader) => reader.read(buf),
+        }
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(&HEADER_MAGIC)?;
+    writer.write_all(&CACHE_FORMAT_VERSION.to_le_bytes())
+}
+
+/// Splits the version header off `reader`.
+///
+/// Caches saved before the header existed have no magic bytes; they are reported as
+/// version 0 and handed back untouched.
+fn read_header<R: Read>(mut reader: R) -> io::Result<(u16, impl Read)> {
+    let mut head = Vec::with_capacity(HEADER_MAGIC.len() + 2);
+    reader
+        .by_ref()
+        .take(HEADER_MAGIC.len() as u64 + 2)
+        .read_to_end(&mut head)?;
+    if head.len() == HEADER_MAGIC.len() + 2 && head.starts_with(&HEADER_MAGIC) {
+        let version = u16::from_le_bytes([head[4], head[5]]);
+        Ok((version, Cursor::new(Vec::new()).chain(reader)))
+    } else {
+        Ok((0, Cursor::new(head).chain(reader)))
+    }
+}
+
+/// Rejects cache layouts this version of the crate cannot read.
+fn check_version(version: u16) -> Result<(), ProxyDbError> {
+    match version {
+        // Version 0 is the headerless layout, which version 1 left unchanged.
+        0 | CACHE_FORMAT_VERSION => Ok(()),
+        version => Err(ProxyDbError::UnsupportedVersion(version)),
+    }
+}
+
+/// Copies the serializable state of `cache_db`, swapping its inner db for an [`EmptyDB`].
+pub(crate) fn detach<ExtDB>(cache_db: &CacheDB<ExtDB>) -> CacheDB<EmptyDB> {
+    let CacheDB {
+        accounts,
+        contracts,
+        logs,
+        block_hashes,
+        ..
+    } = cache_db;
+    CacheDB {
+        accounts: accounts.clone(),
+        contracts: contracts.clone(),
+        logs: logs.clone(),
+        block_hashes: block_hashes.clone(),
+        db: EmptyDB::new(),
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+/// Save lock of one file, see [`lock_path`].
+#[derive(Default)]
+struct PathLock {
+    held: Mutex<bool>,
+    released: Condvar,
+}
+
+/// Holds the save lock of a file until dropped. Unlike a `MutexGuard`, it can be moved
+/// to another thread and held across an `.await`.
+pub(crate) struct PathLockGuard(Arc<PathLock>);
+
+impl Drop for PathLockGuard {
+    fn drop(&mut self) {
+        *self.0.held.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        self.0.released.notify_one();
+    }
+}
+
+/// Takes the save lock of `path`, shared by every path naming the same file, blocking
+/// until the previous holder releases it. Saves from this process to one file thus run
+/// one at a time; other processes are not coordinated with.
+pub(crate) fn lock_path(path: &Path) -> PathLockGuard {
+    static LOCKS: Mutex<BTreeMap<PathBuf, Weak<PathLock>>> = Mutex::new(BTreeMap::new());
+    // The file may not exist yet, so resolve its directory instead.
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let key = match (fs::canonicalize(dir), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    };
+    let lock = {
+        let mut locks = LOCKS.lock().unwrap_or_else(PoisonError::into_inner);
+        locks.retain(|_, lock| lock.strong_count() > 0);
+        match locks.get(&key).and_then(Weak::upgrade) {
+            Some(lock) => lock,
+            None => {
+                let lock = Arc::default();
+                locks.insert(key, Arc::downgrade(&lock));
+                lock
+            }
+        }
+    };
+    let mut held = lock.held.lock().unwrap_or_else(PoisonError::into_inner);
+    while *held {
+        held = lock
+            .released
+            .wait(held)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+    *held = true;
+    drop(held);
+    PathLockGuard(lock)
+}
+
+/// Runs `f` while holding the save lock of `path`, see [`lock_path`].
+pub(crate) fn with_path_lock<T>(path: &Path, f: impl FnOnce() -> T) -> T {
+    let _guard = lock_path(path);
+    f()
+}
+
+/// Runs `write` against a sibling `<path>.tmp` file and renames it over `path` once it
+/// succeeds, so readers only ever see the previous or the new complete cache.
+///
+/// Concurrent saves to the same file from this process wait for each other, see
+/// [`with_path_lock`].
+pub(crate) fn save_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), ProxyDbError>,
+) -> Result<(), ProxyDbError> {
+    with_path_lock(path, || save_atomically_unlocked(path, write))
+}
+
+fn save_atomically_unlocked(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), ProxyDbError>,
+) -> Result<(), ProxyDbError> {
+    let tmp_path = tmp_path(path);
+    let result = (|| {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Serializes `cache_db` into the versioned JSON layout shared by every JSON save helper.
+///
+/// The inner db is not serialized; loading yields a cache over whatever `ExtDB` the
+/// caller deserializes, typically an [`EmptyDB`].
+pub fn cache_db_to_bytes<ExtDB>(cache_db: &CacheDB<ExtDB>) -> Result<Vec<u8>, ProxyDbError> {
+    let mut bytes = Vec::new();
+    write_header(&mut bytes)?;
+    serde_json::to_writer(&mut bytes, &detach(cache_db))?;
+    Ok(bytes)
+}
+
+/// How [`cache_db_to_bytes_with_format`] writes balances, storage slots and block numbers.
+///
+/// Loading accepts either form, whatever the cache was saved with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    /// `0x`-prefixed hex quantities, as in geth dumps. This is revm's own encoding.
+    #[default]
+    Hex,
+    /// Decimal strings.
+    Decimal,
+}
+
+/// [`cache_db_to_bytes`] with the numbers encoded according to `format`.
+pub fn cache_db_to_bytes_with_format<ExtDB>(
+    cache_db: &CacheDB<ExtDB>,
+    format: NumberFormat,
+) -> Result<Vec<u8>, ProxyDbError> {
+    if format == NumberFormat::Hex {
+        return cache_db_to_bytes(cache_db);
+    }
+    let mut json = serde_json::to_value(detach(cache_db))?;
+    if let Some(accounts) = json.get_mut("accounts").and_then(Value::as_object_mut) {
+        for account in accounts.values_mut() {
+            if let Some(balance) = account.pointer_mut("/info/balance") {
+                *balance = to_decimal(balance);
+            }
+            if let Some(storage) = account.get_mut("storage") {
+                *storage = to_decimal_map(storage);
+            }
+        }
+    }
+    if let Some(block_hashes) = json.get_mut("block_hashes") {
+        *block_hashes = to_decimal_keys(block_hashes);
+    }
+    let mut bytes = Vec::new();
+    write_header(&mut bytes)?;
+    serde_json::to_writer(&mut bytes, &json)?;
+    Ok(bytes)
+}
+
+pub fn save_cache_db_to_file_with_format<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+    format: NumberFormat,
+) -> Result<(), ProxyDbError> {
+    let bytes = cache_db_to_bytes_with_format(cache_db, format)?;
+    save_atomically(path.as_ref(), |writer| Ok(writer.write_all(&bytes)?))
+}
+
+fn decimal(hex: &str) -> Option<String> {
+    hex.parse::<U256>().ok().map(|value| value.to_string())
+}
+
+fn to_decimal(value: &Value) -> Value {
+    match value.as_str().and_then(decimal) {
+        Some(decimal) => Value::String(decimal),
+        None => value.clone(),
+    }
+}
+
+fn to_decimal_keys(map: &Value) -> Value {
+    match map.as_object() {
+        Some(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (decimal(key).unwrap_or_else(|| key.clone()), value.clone()))
+                .collect(),
+        ),
+        None => map.clone(),
+    }
+}
+
+fn to_decimal_map(map: &Value) -> Value {
+    match to_decimal_keys(map) {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, to_decimal(&value)))
+                .collect(),
+        ),
+        map => map,
+    }
+}
+
+/// Inverse of [`cache_db_to_bytes`], also accepting gzipped and headerless legacy input.
+pub fn cache_db_from_bytes<ExtDB>(bytes: &[u8]) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    load_cache_db_from_reader(bytes)
+}
+
+pub fn save_cache_db_to_writer<ExtDB, W: Write>(
+    mut writer: W,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), ProxyDbError> {
+    writer.write_all(&cache_db_to_bytes(cache_db)?)?;
+    Ok(())
+}
+
+/// Loads a cache written by [`save_cache_db_to_writer`], gunzipping it first if needed.
+pub fn load_cache_db_from_reader<ExtDB, R: Read>(reader: R) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    let (version, mut reader) = read_header(MaybeGzip::new(reader)?)?;
+    check_version(version)?;
+    let mut json = String::new();
+    let _ = reader.read_to_string(&mut json)?;
+    let cache_db = serde_json::from_str::<CacheDB<ExtDB>>(&json)?;
+    Ok(cache_db)
+}
+
+/// Like [`load_cache_db_from_reader`], but deserializes straight from `reader` instead
+/// of buffering the whole document first, so the file never sits in memory next to the
+/// cache it decodes into. Slower than buffering on small caches.
+pub fn load_cache_db_from_reader_streaming<ExtDB, R: Read>(
+    reader: R,
+) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    let (version, reader) = read_header(MaybeGzip::new(reader)?)?;
+    check_version(version)?;
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let cache_db = CacheDB::<ExtDB>::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(cache_db)
+}
+
+/// Saves the cached state of `cache_db` to `path`, replacing the file atomically.
+///
+/// The inner db is not saved, so anything only held by a nested `CacheDB` below is
+/// dropped; use [`save_flattened_cache_db_to_file`] for layered caches.
+///
+/// Saves to the same file from several threads of this process, through any of the
+/// save helpers, run one after the other: each blocks until the previous one has been
+/// renamed into place, so the file always holds one complete cache. Saves from other
+/// processes are not locked against.
+pub fn save_cache_db_to_file<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), ProxyDbError> {
+    save_atomically(path.as_ref(), |writer| {
+        save_cache_db_to_writer(writer, cache_db)
+    })
+}
+
+/// Replays `fetches` into the cache saved at `path` and saves it back atomically, see
+/// [`apply_fetches_to_cache_db`]. A missing file starts a fresh cache.
+///
+/// The whole cache is loaded for the duration of the call and written back as plain
+/// JSON, even if it was gzipped. On a failing fetch nothing is written.
+pub fn append_fetches_to_file(
+    path: impl AsRef<Path>,
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> Result<(), ProxyDbError> {
+    let path = path.as_ref();
+    // Hold the lock from read to write, so concurrent appends do not drop each other.
+    with_path_lock(path, || {
+        let mut cache_db = match fs::read(path) {
+            Ok(bytes) => cache_db_from_bytes::<EmptyDB>(&bytes)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => CacheDB::new(EmptyDB::new()),
+            Err(err) => return Err(err.into()),
+        };
+        apply_fetches_to_cache_db(&mut cache_db, fetches)?;
+        save_atomically_unlocked(path, |writer| save_cache_db_to_writer(writer, &cache_db))
+    })
+}
+
+/// The chain state a saved cache was forked from, letting a loader reattach a matching
+/// inner db in place of the [`EmptyDB`] the cache is loaded with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkDescriptor {
+    pub chain_id: u64,
+    pub block_number: u64,
+}
+
+#[derive(Serialize)]
+struct ForkedCache<'a> {
+    fork: &'a ForkDescriptor,
+    cache_db: CacheDB<EmptyDB>,
+}
+
+#[derive(Deserialize)]
+struct OwnedForkedCache {
+    fork: ForkDescriptor,
+    cache_db: CacheDB<EmptyDB>,
+}
+
+/// Saves `cache_db` to `path` along with the `fork` it was taken from, replacing the file
+/// atomically. The inner db is not saved, as with [`save_cache_db_to_file`].
+pub fn save_cache_db_with_fork<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+    fork: &ForkDescriptor,
+) -> Result<(), ProxyDbError> {
+    let forked = ForkedCache {
+        fork,
+        cache_db: detach(cache_db),
+    };
+    save_atomically(path.as_ref(), |writer| {
+        write_header(writer)?;
+        serde_json::to_writer(writer, &forked)?;
+        Ok(())
+    })
+}
+
+/// Loads a cache written by [`save_cache_db_with_fork`] and the fork it describes.
+///
+/// To fall back to the chain on a miss, move the cached accounts, contracts, logs and
+/// block hashes into a `CacheDB` over a db built from the descriptor.
+pub fn load_cache_db_with_fork(
+    path: impl AsRef<Path>,
+) -> Result<(CacheDB<EmptyDB>, ForkDescriptor), ProxyDbError> {
+    let (version, reader) = read_header(MaybeGzip::new(File::open(path)?)?)?;
+    check_version(version)?;
+    let forked: OwnedForkedCache = serde_json::from_reader(reader)?;
+    Ok((forked.cache_db, forked.fork))
+}
+
+/// Where and when a simulation bundle was captured. Every field is filled in by the
+/// caller and stored as is.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleMetadata {
+    pub chain_id: u64,
+    pub block_number: u64,
+    /// Unix timestamp, in seconds.
+    pub timestamp: u64,
+}
+
+/// A cache, the fetches that built it and their [`BundleMetadata`], as loaded by
+/// [`load_bundle`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulationBundle {
+    pub metadata: BundleMetadata,
+    pub cache_db: CacheDB<EmptyDB>,
+    /// The fetch log, in the order it was saved.
+    pub fetches: Vec<NewFetch>,
+}
+
+#[derive(Serialize)]
+struct BundleSections<'a> {
+    metadata: &'a BundleMetadata,
+    cache_db: CacheDB<EmptyDB>,
+    fetches: &'a [NewFetch],
+}
+
+/// Saves `cache_db`, the ordered `fetches` log and `metadata` together to `path`, as one
+/// versioned JSON document with a section for each, replacing the file atomically.
+pub fn save_bundle<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+    fetches: &[NewFetch],
+    metadata: &BundleMetadata,
+) -> Result<(), ProxyDbError> {
+    let sections = BundleSections {
+        metadata,
+        cache_db: detach(cache_db),
+        fetches,
+    };
+    save_atomically(path.as_ref(), |writer| {
+        write_header(writer)?;
+        serde_json::to_writer(writer, &sections)?;
+        Ok(())
+    })
+}
+
+/// Loads a bundle written by [`save_bundle`], gunzipping it first if needed.
+pub fn load_bundle(path: impl AsRef<Path>) -> Result<SimulationBundle, ProxyDbError> {
+    let (version, reader) = read_header(MaybeGzip::new(File::open(path)?)?)?;
+    check_version(version)?;
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Like [`save_cache_db_to_file`], but first collapses the caches layered below
+/// `cache_db` into it, see [`flatten_cache_db`].
+pub fn save_flattened_cache_db_to_file<ExtDB: CacheLayers>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), ProxyDbError> {
+    save_cache_db_to_file(path, &flatten_cache_db(cache_db))
+}
+
+/// Gzipped counterpart of [`save_cache_db_to_file`], readable by [`load_cache_db_from_file`].
+pub fn save_cache_db_to_file_compressed<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), ProxyDbError> {
+    save_atomically(path.as_ref(), |writer| {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        save_cache_db_to_writer(&mut encoder, cache_db)?;
+        encoder.finish()?;
+        Ok(())
+    })
+}
+
+/// Async counterpart of [`save_cache_db_to_file`], serializing on a blocking thread and
+/// writing with `tokio::fs`.
+///
+/// Takes the cache by value or behind an `Arc`, so it is not copied on the executor.
+/// Waits for the save lock of `path` like the sync helpers do, without blocking the
+/// executor.
+pub async fn save_cache_db_to_file_async<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: impl Into<Arc<CacheDB<ExtDB>>>,
+) -> Result<(), ProxyDbError>
+where
+    ExtDB: Send + Sync + 'static,
+{
+    let path = path.as_ref().to_path_buf();
+    let cache_db = cache_db.into();
+    let bytes = tokio::task::spawn_blocking(move || cache_db_to_bytes(&cache_db)).await??;
+    let _guard = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || lock_path(&path)).await?
+    };
+    let tmp_path = tmp_path(&path);
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+    }
+    result
+}
+
+/// Async counterpart of [`load_cache_db_from_file`], deserializing on a blocking thread.
+pub async fn load_cache_db_from_file_async<ExtDB>(
+    path: impl AsRef<Path>,
+) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned + Send + 'static,
+{
+    let bytes = tokio::fs::read(path).await?;
+    tokio::task::spawn_blocking(move || cache_db_from_bytes(&bytes)).await?
+}
+
+/// Streaming counterpart of [`load_cache_db_from_file`], see
+/// [`load_cache_db_from_reader_streaming`].
+pub fn load_cache_db_from_file_streaming<ExtDB>(
+    path: impl AsRef<Path>,
+) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    load_cache_db_from_reader_streaming(File::open(path)?)
+}
+
+pub fn load_cache_db_from_file<ExtDB>(
+    path: impl AsRef<Path>,
+) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    cache_db_from_bytes(&fs::read(path)?)
+}
+
+/// Saves only the accounts listed in `addresses` and the contracts they use, see
+/// [`cache_db_subset`].
+pub fn save_cache_db_subset<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+    addresses: &[Address],
+) -> Result<(), ProxyDbError> {
+    save_cache_db_to_file(path, &cache_db_subset(cache_db, addresses))
+}
+
+/// Saves only what changed in `current` since `base` was saved, see [`cache_db_delta`].
+///
+/// The delta is a regular JSON cache, rebuilt on top of its base with
+/// [`load_cache_db_with_deltas`].
+pub fn save_cache_db_delta<ExtDB, OtherDB>(
+    path: impl AsRef<Path>,
+    base: &CacheDB<ExtDB>,
+    current: &CacheDB<OtherDB>,
+) -> Result<(), ProxyDbError> {
+    save_cache_db_to_file(path, &cache_db_delta(base, current))
+}
+
+/// Loads the cache at `base` and merges each delta at `deltas` into it, in order.
+pub fn load_cache_db_with_deltas<ExtDB, P>(
+    base: impl AsRef<Path>,
+    deltas: impl IntoIterator<Item = P>,
+) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+    P: AsRef<Path>,
+{
+    let mut cache_db = load_cache_db_from_file(base)?;
+    for delta in deltas {
+        let delta: CacheDB<EmptyDB> = load_cache_db_from_file(delta)?;
+        merge_cache_dbs(&mut cache_db, &delta);
+    }
+    Ok(cache_db)
+}
+
+/// Everything that turns a base cache into a newer one, removals included.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheDelta {
+    removed_accounts: Vec<Address>,
+    added_accounts: Vec<(Address, DbAccount)>,
+    changed_accounts: Vec<AccountDelta>,
+    removed_contracts: Vec<B256>,
+    contracts: Vec<(B256, Bytecode)>,
+    removed_block_hashes: Vec<U256>,
+    block_hashes: Vec<(U256, B256)>,
+    logs: Vec<Log>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountDelta {
+    address: Address,
+    info: Option<AccountInfo>,
+    account_state: Option<AccountState>,
+    removed_slots: Vec<U256>,
+    slots: Vec<(U256, U256)>,
+}
+
+impl CacheDelta {
+    fn new<ExtDB, OtherDB>(base: &CacheDB<ExtDB>, current: &CacheDB<OtherDB>) -> Self {
+        let diff = diff_cache_dbs(base, current);
+        let mut changed_accounts: Vec<AccountDelta> = diff
+            .changed
+            .into_iter()
+            .map(|account| AccountDelta {
+                address: account.address,
+                info: account.info.map(|(_, new)| new),
+                account_state: None,
+                removed_slots: account
+                    .removed_slots
+                    .into_iter()
+                    .map(|(index, _)| index)
+                    .collect(),
+                slots: account
+                    .added_slots
+                    .into_iter()
+                    .chain(
+                        account
+                            .changed_slots
+                            .into_iter()
+                            .map(|(index, _, new)| (index, new)),
+                    )
+                    .collect(),
+            })
+            .collect();
+        // The diff only compares info and storage, the account state is tracked here.
+        for (address, account) in &current.accounts {
+            let Some(base_account) = base.accounts.get(address) else {
+                continue;
+            };
+            if base_account.account_state == account.account_state {
+                continue;
+            }
+            match changed_accounts
+                .iter_mut()
+                .find(|delta| delta.address == *address)
+            {
+                Some(delta) => delta.account_state = Some(account.account_state.clone()),
+                None => changed_accounts.push(AccountDelta {
+                    address: *address,
+                    info: None,
+                    account_state: Some(account.account_state.clone()),
+                    removed_slots: Vec::new(),
+                    slots: Vec::new(),
+                }),
+            }
+        }
+        Self {
+            removed_accounts: diff.removed,
+            added_accounts: diff
+                .added
+                .into_iter()
+                .map(|address| (address, current.accounts[&address].clone()))
+                .collect(),
+            changed_accounts,
+            removed_contracts: base
+                .contracts
+                .keys()
+                .filter(|code_hash| !current.contracts.contains_key(*code_hash))
+                .copied()
+                .collect(),
+            contracts: current
+                .contracts
+                .iter()
+                .filter(|(code_hash, _)| !base.contracts.contains_key(*code_hash))
+                .map(|(code_hash, bytecode)| (*code_hash, bytecode.clone()))
+                .collect(),
+            removed_block_hashes: base
+                .block_hashes
+                .keys()
+                .filter(|number| !current.block_hashes.contains_key(*number))
+                .copied()
+                .collect(),
+            block_hashes: current
+                .block_hashes
+                .iter()
+                .filter(|(number, hash)| base.block_hashes.get(*number) != Some(*hash))
+                .map(|(number, hash)| (*number, *hash))
+                .collect(),
+            logs: current.logs.clone(),
+        }
+    }
+
+    fn apply<ExtDB>(self, cache_db: &mut CacheDB<ExtDB>) {
+        for address in self.removed_accounts {
+            cache_db.accounts.remove(&address);
+        }
+        cache_db.accounts.extend(self.added_accounts);
+        for delta in self.changed_accounts {
+            let account = cache_db.accounts.entry(delta.address).or_default();
+            if let Some(info) = delta.info {
+                account.info = info;
+            }
+            if let Some(account_state) = delta.account_state {
+                account.account_state = account_state;
+            }
+            for index in delta.removed_slots {
+                account.storage.remove(&index);
+            }
+            account.storage.extend(delta.slots);
+        }
+        for code_hash in self.removed_contracts {
+            cache_db.contracts.remove(&code_hash);
+        }
+        cache_db.contracts.extend(self.contracts);
+        for number in self.removed_block_hashes {
+            cache_db.block_hashes.remove(&number);
+        }
+        cache_db.block_hashes.extend(self.block_hashes);
+        cache_db.logs = self.logs;
+    }
+}
+
+/// Saves the differences between `base` and `cache_db`, including removed accounts,
+/// slots, contracts and block hashes, so [`load_cache_db_applying`] rebuilds `cache_db`
+/// exactly from the same base.
+///
+/// Unlike [`save_cache_db_delta`], the file is not a cache of its own and is only
+/// meaningful on top of `base`.
+pub fn save_cache_db_relative_to<ExtDB, OtherDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+    base: &CacheDB<OtherDB>,
+) -> Result<(), ProxyDbError> {
+    let delta = CacheDelta::new(base, cache_db);
+    save_atomically(path.as_ref(), |writer| {
+        write_header(writer)?;
+        serde_json::to_writer(writer, &delta)?;
+        Ok(())
+    })
+}
+
+/// Rebuilds the cache saved by [`save_cache_db_relative_to`] on top of a copy of `base`.
+pub fn load_cache_db_applying<ExtDB>(
+    path: impl AsRef<Path>,
+    base: &CacheDB<ExtDB>,
+) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+    let (version, reader) = read_header(MaybeGzip::new(File::open(path)?)?)?;
+    check_version(version)?;
+    let delta: CacheDelta = serde_json::from_reader(reader)?;
+    let mut cache_db = detach(base);
+    delta.apply(&mut cache_db);
+    Ok(cache_db)
+}
+
+const SHARD_PREFIX: &str = "shard-";
+const SHARD_META: &str = "meta.json";
+
+/// Index of the shard holding `address` when a cache is saved into `shards` shards,
+/// bucketing addresses by their two high bytes.
+pub fn shard_index(address: Address, shards: usize) -> usize {
+    let high = u16::from_be_bytes([address[0], address[1]]) as usize;
+    (high * shards.max(1)) >> 16
+}
+
+/// Saves `cache_db` into `dir` as `shards` JSON caches named `shard-<index>.json`, one per
+/// [`shard_index`], next to a `meta.json` cache holding its contracts, logs and block
+/// hashes. Shards left over by a previous save into more shards are removed once every
+/// new file is written.
+///
+/// Each file is a regular cache, so a single shard can be loaded on its own with
+/// [`load_cache_db_from_file`].
+pub fn save_cache_db_sharded<ExtDB>(
+    dir: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+    shards: usize,
+) -> Result<(), ProxyDbError> {
+    let dir = dir.as_ref();
+    let shards = shards.max(1);
+    fs::create_dir_all(dir)?;
+    let mut buckets: Vec<CacheDB<EmptyDB>> = (0..shards)
+        .map(|_| {
+            let mut shard = CacheDB::new(EmptyDB::new());
+            shard.contracts.clear();
+            shard
+        })
+        .collect();
+    for (address, account) in &cache_db.accounts {
+        buckets[shard_index(*address, shards)]
+            .accounts
+            .insert(*address, account.clone());
+    }
+    for (index, shard) in buckets.iter().enumerate() {
+        save_cache_db_to_file(dir.join(format!("{SHARD_PREFIX}{index}.json")), shard)?;
+    }
+    let mut meta = detach(cache_db);
+    meta.accounts.clear();
+    save_cache_db_to_file(dir.join(SHARD_META), &meta)?;
+    for (index, path) in shard_paths(dir)? {
+        if index >= shards {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reassembles a cache saved by [`save_cache_db_sharded`].
+pub fn load_cache_db_sharded<ExtDB>(dir: impl AsRef<Path>) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    let dir = dir.as_ref();
+    let mut cache_db = load_cache_db_from_file(dir.join(SHARD_META))?;
+    for (_, path) in shard_paths(dir)? {
+        let shard: CacheDB<EmptyDB> = load_cache_db_from_file(path)?;
+        cache_db.accounts.extend(shard.accounts);
+    }
+    Ok(cache_db)
+}
+
+/// The shard files in `dir` with their index.
+fn shard_paths(dir: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let index = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(SHARD_PREFIX)?.strip_suffix(".json"))
+            .and_then(|index| index.parse().ok());
+        if let Some(index) = index {
+            paths.push((index, path));
+        }
+    }
+    Ok(paths)
+}
+
+/// Binary counterpart of [`save_cache_db_to_writer`].
+///
+/// Encoding and decoding are noticeably faster than JSON, but numbers are stored at
+/// full width, so caches dominated by small balances and slot values can come out
+/// larger than their JSON form.
+pub fn save_cache_db_to_writer_bincode<ExtDB, W: Write>(
+    mut writer: W,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), ProxyDbError> {
+    write_header(&mut writer)?;
+    bincode::serialize_into(writer, &detach(cache_db))?;
+    Ok(())
+}
+
+pub fn load_cache_db_from_reader_bincode<ExtDB, R: Read>(
+    reader: R,
+) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    let (version, reader) = read_header(MaybeGzip::new(reader)?)?;
+    check_version(version)?;
+    let cache_db = bincode::deserialize_from::<_, CacheDB<ExtDB>>(reader)?;
+    Ok(cache_db)
+}
+
+pub fn save_cache_db_to_file_bincode<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), ProxyDbError> {
+    save_atomically(path.as_ref(), |writer| {
+        save_cache_db_to_writer_bincode(writer, cache_db)
+    })
+}
+
+/// Gzipped counterpart of [`save_cache_db_to_file_bincode`], readable by
+/// [`load_cache_db_from_file_bincode`].
+pub fn save_cache_db_to_file_bincode_compressed<ExtDB>(
+    path: impl AsRef<Path>,
+    cache_db: &CacheDB<ExtDB>,
+) -> Result<(), ProxyDbError> {
+    save_atomically(path.as_ref(), |writer| {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        save_cache_db_to_writer_bincode(&mut encoder, cache_db)?;
+        encoder.finish()?;
+        Ok(())
+    })
+}
+
+pub fn load_cache_db_from_file_bincode<ExtDB>(
+    path: impl AsRef<Path>,
+) -> Result<CacheDB<ExtDB>, ProxyDbError>
+where
+    ExtDB: DeserializeOwned,
+{
+    let file = File::open(path)?;
+    load_cache_db_from_reader_bincode(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DbStats, FetchKey, FetchKind, UniqueStats};
+
+    #[test]
+    fn failed_save_leaves_the_previous_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        save_cache_db_to_file(&path, &CacheDB::new(EmptyDB::new())).unwrap();
+        let saved = fs::read(&path).unwrap();
+
+        let result = save_atomically(&path, |writer| {
+            writer.write_all(b"{\"accounts\":")?;
+            Err(io::Error::other("disk full").into())
+        });
+        assert!(matches!(result, Err(ProxyDbError::Io(_))));
+        assert_eq!(fs::read(&path).unwrap(), saved);
+        assert!(!tmp_path(&path).exists());
+    }
+
+    #[test]
+    fn every_fetch_variant_round_trips() {
+        let address = Address::with_last_byte(1);
+        let bytecode = Bytecode::new_raw([0x60, 0x01, 0x00].to_vec().into());
+        let account_info = AccountInfo {
+            balance: U256::from(7),
+            nonce: 2,
+            ..AccountInfo::from_bytecode(bytecode.clone())
+        };
+        let fetches = [
+            NewFetch::Basic {
+                address,
+                account_info: account_info.clone(),
+            },
+            NewFetch::BasicMissing { address },
+            NewFetch::Storage {
+                address,
+                index: U256::MAX,
+                value: U256::from(1),
+            },
+            NewFetch::CodeByHash {
+                code_hash: bytecode.hash_slow(),
+                bytecode,
+            },
+            NewFetch::BlockHash {
+                number: u64::MAX,
+                hash: B256::repeat_byte(1),
+            },
+            NewFetch::CacheHit {
+                key: FetchKey::Storage(address, U256::from(1)),
+            },
+            NewFetch::BasicWrite {
+                address,
+                account_info,
+            },
+            NewFetch::StorageWrite {
+                address,
+                index: U256::from(1),
+                value: U256::ZERO,
+            },
+            NewFetch::Summary {
+                stats: DbStats {
+                    basic_reads: 1,
+                    unique: Some(UniqueStats {
+                        accounts: 1,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            },
+            NewFetch::Error {
+                kind: FetchKind::Storage,
+                address: Some(address),
+                index: Some(U256::from(1)),
+                message: "backend unavailable".into(),
+            },
+        ];
+        for fetch in fetches {
+            let json = serde_json::to_string(&fetch).unwrap();
+            assert_eq!(serde_json::from_str::<NewFetch>(&json).unwrap(), fetch);
+            let bytes = bincode::serialize(&fetch).unwrap();
+            assert_eq!(bincode::deserialize::<NewFetch>(&bytes).unwrap(), fetch);
+        }
+    }
+}
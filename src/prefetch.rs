@@ -0,0 +1,201 @@
+use futures::stream::{self, StreamExt};
+use revm::{
+    db::{AccountState, CacheDB, DbAccount},
+    primitives::{hash_map::Entry, Address, Bytecode, HashSet, B256, KECCAK_EMPTY, U256},
+    DatabaseRef,
+};
+
+use crate::{AsyncDatabaseRef, FetchKey};
+
+/// Loads every account and storage slot of `access_list` from the inner db of
+/// `cache_db` ahead of execution, along with the bytecode of contract accounts.
+///
+/// Keys already present in the cache are left untouched and not fetched again.
+pub fn prefetch_into_cache_db<ExtDB: DatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    access_list: &[(Address, Vec<U256>)],
+) -> Result<(), ExtDB::Error> {
+    let CacheDB {
+        accounts,
+        contracts,
+        db,
+        ..
+    } = cache_db;
+    for (address, slots) in access_list {
+        let account = match accounts.entry(*address) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                db.basic_ref(*address)?
+                    .map(|info| DbAccount {
+                        info,
+                        ..Default::default()
+                    })
+                    .unwrap_or_else(DbAccount::new_not_existing),
+            ),
+        };
+        if account.account_state == AccountState::NotExisting {
+            continue;
+        }
+        let code_hash = account.info.code_hash;
+        if code_hash != KECCAK_EMPTY && !code_hash.is_zero() {
+            if let Entry::Vacant(entry) = contracts.entry(code_hash) {
+                entry.insert(match &account.info.code {
+                    Some(code) => code.clone(),
+                    None => db.code_by_hash_ref(code_hash)?,
+                });
+            }
+        }
+        if account.account_state.is_storage_cleared() {
+            // Every slot reads as zero without consulting the inner db.
+            continue;
+        }
+        for index in slots {
+            if let Entry::Vacant(entry) = account.storage.entry(*index) {
+                entry.insert(db.storage_ref(*address, *index)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What [`prefetch_into_cache_db_async`] does when a lookup fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrefetchErrorPolicy {
+    /// Stop at the first failure and return its error, cancelling the lookups in flight.
+    #[default]
+    Abort,
+    /// Keep going and return every failure once done. Failed keys stay out of the cache.
+    Collect,
+}
+
+enum Lookup {
+    Code(B256),
+    Storage(Address, U256),
+}
+
+enum Value {
+    Code(Bytecode),
+    Storage(U256),
+}
+
+/// Async, concurrent counterpart of [`prefetch_into_cache_db`], running up to
+/// `concurrency` lookups against the inner db at once.
+///
+/// Accounts are loaded in a first wave, then their code and storage in a second one.
+/// Under [`PrefetchErrorPolicy::Collect`] the failed keys are returned with their
+/// errors, empty if everything was loaded. A `concurrency` of zero is treated as one.
+pub async fn prefetch_into_cache_db_async<ExtDB: AsyncDatabaseRef>(
+    cache_db: &mut CacheDB<ExtDB>,
+    access_list: &[(Address, Vec<U256>)],
+    concurrency: usize,
+    policy: PrefetchErrorPolicy,
+) -> Result<Vec<(FetchKey, ExtDB::Error)>, ExtDB::Error> {
+    let concurrency = concurrency.max(1);
+    let CacheDB {
+        accounts,
+        contracts,
+        db,
+        ..
+    } = cache_db;
+    let db = &*db;
+    let mut failures = Vec::new();
+    let mut fail = |key: FetchKey, err: ExtDB::Error| match policy {
+        PrefetchErrorPolicy::Abort => Err(err),
+        PrefetchErrorPolicy::Collect => {
+            failures.push((key, err));
+            Ok(())
+        }
+    };
+
+    let mut missing: Vec<Address> = access_list
+        .iter()
+        .map(|(address, _)| *address)
+        .filter(|address| !accounts.contains_key(address))
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    let mut basics = stream::iter(missing)
+        .map(|address| async move { (address, db.basic_async_ref(address).await) })
+        .buffer_unordered(concurrency);
+    while let Some((address, result)) = basics.next().await {
+        match result {
+            Ok(info) => {
+                let account = info
+                    .map(|info| DbAccount {
+                        info,
+                        ..Default::default()
+                    })
+                    .unwrap_or_else(DbAccount::new_not_existing);
+                accounts.insert(address, account);
+            }
+            Err(err) => fail(FetchKey::Basic(address), err)?,
+        }
+    }
+    drop(basics);
+
+    let mut lookups = Vec::new();
+    let mut code_hashes: HashSet<B256> = HashSet::default();
+    for (address, slots) in access_list {
+        let Some(account) = accounts.get(address) else {
+            continue;
+        };
+        if account.account_state == AccountState::NotExisting {
+            continue;
+        }
+        let code_hash = account.info.code_hash;
+        if code_hash != KECCAK_EMPTY && !code_hash.is_zero() && !contracts.contains_key(&code_hash)
+        {
+            match &account.info.code {
+                Some(code) => {
+                    contracts.insert(code_hash, code.clone());
+                }
+                None if code_hashes.insert(code_hash) => lookups.push(Lookup::Code(code_hash)),
+                None => {}
+            }
+        }
+        if account.account_state.is_storage_cleared() {
+            continue;
+        }
+        lookups.extend(
+            slots
+                .iter()
+                .filter(|index| !account.storage.contains_key(*index))
+                .map(|index| Lookup::Storage(*address, *index)),
+        );
+    }
+    let mut values = stream::iter(lookups)
+        .map(|lookup| async move {
+            match lookup {
+                Lookup::Code(code_hash) => (
+                    FetchKey::CodeByHash(code_hash),
+                    db.code_by_hash_async_ref(code_hash).await.map(Value::Code),
+                ),
+                Lookup::Storage(address, index) => (
+                    FetchKey::Storage(address, index),
+                    db.storage_async_ref(address, index)
+                        .await
+                        .map(Value::Storage),
+                ),
+            }
+        })
+        .buffer_unordered(concurrency);
+    while let Some((key, result)) = values.next().await {
+        match (key, result) {
+            (FetchKey::CodeByHash(code_hash), Ok(Value::Code(bytecode))) => {
+                contracts.insert(code_hash, bytecode);
+            }
+            (FetchKey::Storage(address, index), Ok(Value::Storage(value))) => {
+                accounts
+                    .entry(address)
+                    .or_default()
+                    .storage
+                    .entry(index)
+                    .or_insert(value);
+            }
+            (key, Err(err)) => fail(key, err)?,
+            _ => {}
+        }
+    }
+    drop(values);
+    Ok(failures)
+}
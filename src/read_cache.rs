@@ -0,0 +1,95 @@
+use std::{hash::Hash, sync::Mutex};
+
+use revm::primitives::{AccountInfo, Address, Bytecode, B256, U256};
+
+use crate::bounded::KeyMap;
+
+/// Results already fetched from the inner db, served again without hitting it.
+///
+/// Only successful lookups are cached, so an inner db error is retried on the next call.
+#[derive(Debug, Default)]
+pub(crate) struct ReadCache {
+    accounts: Mutex<KeyMap<Address, Option<AccountInfo>>>,
+    storage: Mutex<KeyMap<(Address, U256), U256>>,
+    contracts: Mutex<KeyMap<B256, Bytecode>>,
+    block_hashes: Mutex<KeyMap<u64, B256>>,
+}
+
+impl ReadCache {
+    /// Keeps at most `capacity` entries of each kind, evicting the least recently used
+    /// ones first.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            accounts: Mutex::new(KeyMap::with_capacity(capacity)),
+            storage: Mutex::new(KeyMap::with_capacity(capacity)),
+            contracts: Mutex::new(KeyMap::with_capacity(capacity)),
+            block_hashes: Mutex::new(KeyMap::with_capacity(capacity)),
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        clear(&self.accounts);
+        clear(&self.storage);
+        clear(&self.contracts);
+        clear(&self.block_hashes);
+    }
+
+    pub(crate) fn basic(&self, address: Address) -> Option<Option<AccountInfo>> {
+        get(&self.accounts, &address)
+    }
+
+    pub(crate) fn insert_basic(&self, address: Address, account_info: Option<AccountInfo>) {
+        insert(&self.accounts, address, account_info)
+    }
+
+    pub(crate) fn storage(&self, address: Address, index: U256) -> Option<U256> {
+        get(&self.storage, &(address, index))
+    }
+
+    pub(crate) fn insert_storage(&self, address: Address, index: U256, value: U256) {
+        insert(&self.storage, (address, index), value)
+    }
+
+    /// Forgets the account at `address` and all of its storage, e.g. after a commit
+    /// changed them in the inner db.
+    pub(crate) fn invalidate_account(&self, address: Address) {
+        if let Ok(mut accounts) = self.accounts.lock() {
+            accounts.remove(&address);
+        }
+        if let Ok(mut storage) = self.storage.lock() {
+            storage.retain(|(slot_address, _)| *slot_address != address);
+        }
+    }
+
+    pub(crate) fn code_by_hash(&self, code_hash: B256) -> Option<Bytecode> {
+        get(&self.contracts, &code_hash)
+    }
+
+    pub(crate) fn insert_code_by_hash(&self, code_hash: B256, bytecode: Bytecode) {
+        insert(&self.contracts, code_hash, bytecode)
+    }
+
+    pub(crate) fn block_hash(&self, number: u64) -> Option<B256> {
+        get(&self.block_hashes, &number)
+    }
+
+    pub(crate) fn insert_block_hash(&self, number: u64, hash: B256) {
+        insert(&self.block_hashes, number, hash)
+    }
+}
+
+fn get<K: Eq + Hash, V: Clone>(map: &Mutex<KeyMap<K, V>>, key: &K) -> Option<V> {
+    map.lock().ok()?.get(key).cloned()
+}
+
+fn insert<K: Eq + Hash, V>(map: &Mutex<KeyMap<K, V>>, key: K, value: V) {
+    if let Ok(mut map) = map.lock() {
+        map.insert(key, value);
+    }
+}
+
+fn clear<K: Eq + Hash, V>(map: &Mutex<KeyMap<K, V>>) {
+    if let Ok(mut map) = map.lock() {
+        map.clear();
+    }
+}
@@ -0,0 +1,554 @@
+use revm::{
+    db::{AccountState, CacheDB, DbAccount, EmptyDB},
+    primitives::{AccountInfo, Address, Bytecode, HashMap, HashSet, B256, KECCAK_EMPTY, U256},
+    DatabaseRef,
+};
+
+use crate::{cache::check_code_hash, FetchKey, NewFetch, ProxyDbError};
+
+/// A single mutation of a `CacheDB`, as derived from a recorded fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheOp {
+    /// Also inserts the inline code of `info`, if any, into the contracts map.
+    InsertAccount {
+        address: Address,
+        info: AccountInfo,
+    },
+    /// Caches `address` as not existing, unless the cache already holds it.
+    InsertMissingAccount {
+        address: Address,
+    },
+    InsertStorage {
+        address: Address,
+        index: U256,
+        value: U256,
+    },
+    InsertCode {
+        code_hash: B256,
+        bytecode: Bytecode,
+    },
+    InsertBlockHash {
+        number: u64,
+        hash: B256,
+    },
+}
+
+impl CacheOp {
+    /// Storage for an address whose account has not been inserted yet lands on a
+    /// default account, which a later `InsertAccount` fills in without touching storage.
+    ///
+    /// Code, inline or not, whose hash does not match the one it is inserted under is
+    /// rejected with [`ProxyDbError::CorruptCache`], leaving `cache_db` unchanged.
+    pub fn apply<ExtDB>(self, cache_db: &mut CacheDB<ExtDB>) -> Result<(), ProxyDbError> {
+        match self {
+            Self::InsertAccount { address, info } => {
+                // `insert_account_info` ignores empty inline code and hashes the rest
+                // itself when the hash is missing.
+                if let Some(code) = info.code.as_ref().filter(|code| !code.is_empty()) {
+                    if info.code_hash != KECCAK_EMPTY && !info.code_hash.is_zero() {
+                        check_code_hash(info.code_hash, code)?;
+                    }
+                }
+                cache_db.insert_account_info(address, info);
+                // An account seen existing wins over one seen missing, in either order.
+                let account = cache_db.accounts.entry(address).or_default();
+                if account.account_state == AccountState::NotExisting {
+                    account.account_state = AccountState::None;
+                }
+            }
+            Self::InsertMissingAccount { address } => {
+                cache_db
+                    .accounts
+                    .entry(address)
+                    .or_insert_with(DbAccount::new_not_existing);
+            }
+            Self::InsertStorage {
+                address,
+                index,
+                value,
+            } => {
+                cache_db
+                    .accounts
+                    .entry(address)
+                    .or_default()
+                    .storage
+                    .insert(index, value);
+            }
+            Self::InsertCode {
+                code_hash,
+                bytecode,
+            } => {
+                check_code_hash(code_hash, &bytecode)?;
+                cache_db.contracts.insert(code_hash, bytecode);
+            }
+            Self::InsertBlockHash { number, hash } => {
+                cache_db.block_hashes.insert(U256::from(number), hash);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CacheOp {
+    /// The mutation recording `fetch` into a cache, if it carries a value.
+    pub fn from_fetch(fetch: NewFetch) -> Option<Self> {
+        let op = match fetch {
+            NewFetch::Basic {
+                address,
+                account_info,
+            } => Self::InsertAccount {
+                address,
+                info: account_info,
+            },
+            NewFetch::BasicMissing { address } => Self::InsertMissingAccount { address },
+            NewFetch::Storage {
+                address,
+                index,
+                value,
+            } => Self::InsertStorage {
+                address,
+                index,
+                value,
+            },
+            NewFetch::CodeByHash {
+                code_hash,
+                bytecode,
+            } => Self::InsertCode {
+                code_hash,
+                bytecode,
+            },
+            NewFetch::BlockHash { number, hash } => Self::InsertBlockHash { number, hash },
+            NewFetch::Error { .. }
+            | NewFetch::CacheHit { .. }
+            | NewFetch::BasicWrite { .. }
+            | NewFetch::StorageWrite { .. }
+            | NewFetch::Summary { .. } => return None,
+        };
+        Some(op)
+    }
+}
+
+/// Turns recorded fetches into the cache mutations [`apply_fetches_to_cache_db`] performs,
+/// so they can be filtered or reordered first. Failed lookups, cache hits, writes and
+/// summaries yield no mutation.
+pub fn fetches_to_ops(
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> impl Iterator<Item = CacheOp> {
+    fetches.into_iter().filter_map(CacheOp::from_fetch)
+}
+
+/// What replaying does with storage recorded for an account no fetch covers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrphanStoragePolicy {
+    /// Land the storage on a default account, for captures that legitimately read
+    /// storage without the account.
+    #[default]
+    Allow,
+    /// Fail with [`ProxyDbError::OrphanStorage`] unless the account was fetched, as
+    /// existing or missing, or already cached. Catches storage recorded under the wrong
+    /// address. The error names the lowest orphan slot by address, then index.
+    Reject,
+}
+
+/// Inserts recorded fetches into `cache_db`, see [`CacheOp::apply`].
+///
+/// The result does not depend on the order of `fetches`, which may interleave
+/// arbitrarily when recorded from several threads: storage of an account not cached yet
+/// is held back until its account fetch arrives, or lands on a default account at the
+/// end, and an account fetched as existing wins over one fetched as missing. Only values
+/// recorded twice for the same key depend on order, the last one wins.
+///
+/// Stops at the first fetch that fails to apply, keeping the ones before it.
+pub fn apply_fetches_to_cache_db<ExtDB>(
+    cache_db: &mut CacheDB<ExtDB>,
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> Result<(), ProxyDbError> {
+    apply_fetches_to_cache_db_with_policy(cache_db, fetches, OrphanStoragePolicy::Allow)
+}
+
+/// Like [`apply_fetches_to_cache_db`], applying `policy` to orphan storage once every
+/// fetch is in. Rejected orphan storage is left out of `cache_db`.
+pub fn apply_fetches_to_cache_db_with_policy<ExtDB>(
+    cache_db: &mut CacheDB<ExtDB>,
+    fetches: impl IntoIterator<Item = NewFetch>,
+    policy: OrphanStoragePolicy,
+) -> Result<(), ProxyDbError> {
+    let mut pending: HashMap<Address, Vec<(U256, U256)>> = HashMap::default();
+    let result = fetches_to_ops(fetches).try_for_each(|op| match op {
+        CacheOp::InsertStorage {
+            address,
+            index,
+            value,
+        } if !cache_db.accounts.contains_key(&address) => {
+            pending.entry(address).or_default().push((index, value));
+            Ok(())
+        }
+        CacheOp::InsertAccount { address, .. } | CacheOp::InsertMissingAccount { address } => {
+            op.apply(cache_db)?;
+            if let Some(storage) = pending.remove(&address) {
+                let account = cache_db.accounts.entry(address).or_default();
+                account.storage.extend(storage);
+            }
+            Ok(())
+        }
+        op => op.apply(cache_db),
+    });
+    if policy == OrphanStoragePolicy::Reject && result.is_ok() {
+        let orphan = pending
+            .iter()
+            .flat_map(|(address, storage)| storage.iter().map(|(index, _)| (*address, *index)))
+            .min();
+        if let Some((address, index)) = orphan {
+            return Err(ProxyDbError::OrphanStorage { address, index });
+        }
+    }
+    for (address, storage) in pending {
+        cache_db
+            .accounts
+            .entry(address)
+            .or_default()
+            .storage
+            .extend(storage);
+    }
+    result
+}
+
+/// Builds an offline cache holding exactly the recorded `fetches`.
+///
+/// Fetches may come in any order the proxy emitted them, see [`apply_fetches_to_cache_db`].
+pub fn cache_db_from_fetches(
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+    cache_db_from_fetches_with_policy(fetches, OrphanStoragePolicy::Allow)
+}
+
+/// Like [`cache_db_from_fetches`], applying `policy` to orphan storage.
+pub fn cache_db_from_fetches_with_policy(
+    fetches: impl IntoIterator<Item = NewFetch>,
+    policy: OrphanStoragePolicy,
+) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    apply_fetches_to_cache_db_with_policy(&mut cache_db, fetches, policy)?;
+    Ok(cache_db)
+}
+
+/// Builds the cache a run left behind: the recorded reads, as by
+/// [`cache_db_from_fetches`], overwritten in order by the writes recorded with
+/// [`RecordingDb::record_writes`](crate::RecordingDb::record_writes).
+pub fn post_state_from_fetches(
+    fetches: impl IntoIterator<Item = NewFetch>,
+) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+    let (writes, reads): (Vec<_>, Vec<_>) = fetches.into_iter().partition(|fetch| {
+        matches!(
+            fetch,
+            NewFetch::BasicWrite { .. } | NewFetch::StorageWrite { .. }
+        )
+    });
+    let mut cache_db = cache_db_from_fetches(reads)?;
+    for write in writes {
+        let op = match write {
+            NewFetch::BasicWrite {
+                address,
+                account_info,
+            } => CacheOp::InsertAccount {
+                address,
+                info: account_info,
+            },
+            NewFetch::StorageWrite {
+                address,
+                index,
+                value,
+            } => CacheOp::InsertStorage {
+                address,
+                index,
+                value,
+            },
+            _ => continue,
+        };
+        op.apply(&mut cache_db)?;
+    }
+    Ok(cache_db)
+}
+
+/// A recorded key that a replayed cache answers differently than the live db.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayMismatch {
+    Basic {
+        address: Address,
+        replayed: Option<AccountInfo>,
+        live: Option<AccountInfo>,
+    },
+    Storage {
+        address: Address,
+        index: U256,
+        replayed: U256,
+        live: U256,
+    },
+    Code {
+        code_hash: B256,
+        replayed: Bytecode,
+        live: Bytecode,
+    },
+    BlockHash {
+        number: u64,
+        replayed: B256,
+        live: B256,
+    },
+}
+
+/// Reads every key in `recorded` from both `replayed` and `live` and returns the reads
+/// that differ, in recorded order. Code is compared for accounts too, so code missing
+/// from the replayed cache is caught even if its `CodeByHash` fetch was never recorded.
+///
+/// Pass the inner db of a proxy, not the proxy itself, unless the verification reads
+/// should be recorded as well. Failed lookups and cache hits in `recorded` are skipped.
+pub fn verify_replay<LiveDB: DatabaseRef>(
+    recorded: &[NewFetch],
+    replayed: &CacheDB<EmptyDB>,
+    live: &LiveDB,
+) -> Result<Vec<ReplayMismatch>, LiveDB::Error> {
+    let keys = recorded.iter().filter_map(|fetch| match fetch {
+        NewFetch::Basic { address, .. } | NewFetch::BasicMissing { address } => {
+            Some(FetchKey::Basic(*address))
+        }
+        NewFetch::Storage { address, index, .. } => Some(FetchKey::Storage(*address, *index)),
+        NewFetch::CodeByHash { code_hash, .. } => Some(FetchKey::CodeByHash(*code_hash)),
+        NewFetch::BlockHash { number, .. } => Some(FetchKey::BlockHash(*number)),
+        NewFetch::Error { .. }
+        | NewFetch::CacheHit { .. }
+        | NewFetch::BasicWrite { .. }
+        | NewFetch::StorageWrite { .. }
+        | NewFetch::Summary { .. } => None,
+    });
+    compare_keys(keys, replayed, live)
+}
+
+fn compare_keys<LiveDB: DatabaseRef>(
+    keys: impl IntoIterator<Item = FetchKey>,
+    replayed: &CacheDB<EmptyDB>,
+    live: &LiveDB,
+) -> Result<Vec<ReplayMismatch>, LiveDB::Error> {
+    let mut mismatches = Vec::new();
+    let check_code = |code_hash: B256, mismatches: &mut Vec<ReplayMismatch>| {
+        let Ok(replayed) = replayed.code_by_hash_ref(code_hash);
+        let live = live.code_by_hash_ref(code_hash)?;
+        if replayed.original_bytes() != live.original_bytes() {
+            mismatches.push(ReplayMismatch::Code {
+                code_hash,
+                replayed,
+                live,
+            });
+        }
+        Ok(())
+    };
+    for key in keys {
+        match key {
+            FetchKey::Basic(address) => {
+                let Ok(replayed_info) = replayed.basic_ref(address);
+                let live_info = live.basic_ref(address)?;
+                if replayed_info != live_info {
+                    mismatches.push(ReplayMismatch::Basic {
+                        address,
+                        replayed: replayed_info,
+                        live: live_info,
+                    });
+                } else if let Some(info) = live_info.filter(|info| !info.is_empty_code_hash()) {
+                    check_code(info.code_hash, &mut mismatches)?;
+                }
+            }
+            FetchKey::Storage(address, index) => {
+                let Ok(replayed_value) = replayed.storage_ref(address, index);
+                let live_value = live.storage_ref(address, index)?;
+                if replayed_value != live_value {
+                    mismatches.push(ReplayMismatch::Storage {
+                        address,
+                        index,
+                        replayed: replayed_value,
+                        live: live_value,
+                    });
+                }
+            }
+            FetchKey::CodeByHash(code_hash) => check_code(code_hash, &mut mismatches)?,
+            FetchKey::BlockHash(number) => {
+                let Ok(replayed_hash) = replayed.block_hash_ref(number);
+                let live_hash = live.block_hash_ref(number)?;
+                if replayed_hash != live_hash {
+                    mismatches.push(ReplayMismatch::BlockHash {
+                        number,
+                        replayed: replayed_hash,
+                        live: live_hash,
+                    });
+                }
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// How a replayed cache drifts from the live db over an access list, see
+/// [`divergence_report`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DivergenceReport {
+    /// Keys the replayed cache holds but answers differently than the live db.
+    pub mismatches: Vec<ReplayMismatch>,
+    /// Accounts and slots of the access list the replayed cache does not hold.
+    pub missing_from_replay: Vec<FetchKey>,
+    /// Accounts and slots the replayed cache holds outside the access list.
+    pub unexpected_in_replay: Vec<FetchKey>,
+}
+
+impl DivergenceReport {
+    /// Whether the replayed cache matches the live db exactly over the access list.
+    pub fn is_empty(&self) -> bool {
+        self.mismatches.is_empty()
+            && self.missing_from_replay.is_empty()
+            && self.unexpected_in_replay.is_empty()
+    }
+}
+
+/// Compares `replayed` against `live` over the accounts and slots of `access_list`, as
+/// run from the same transaction, plus every contract and block hash `replayed` holds.
+///
+/// Keys only one side covers are listed apart rather than compared. Like
+/// [`verify_replay`], pass the inner db of a proxy unless the reads should be recorded.
+pub fn divergence_report<LiveDB: DatabaseRef>(
+    live: &LiveDB,
+    replayed: &CacheDB<EmptyDB>,
+    access_list: &[(Address, Vec<U256>)],
+) -> Result<DivergenceReport, LiveDB::Error> {
+    let mut report = DivergenceReport::default();
+    let mut keys = Vec::new();
+    let mut listed: HashMap<Address, HashSet<U256>> = HashMap::default();
+    for (address, slots) in access_list {
+        listed
+            .entry(*address)
+            .or_default()
+            .extend(slots.iter().copied());
+        let Some(account) = replayed.accounts.get(address) else {
+            report.missing_from_replay.push(FetchKey::Basic(*address));
+            continue;
+        };
+        keys.push(FetchKey::Basic(*address));
+        for index in slots {
+            let key = FetchKey::Storage(*address, *index);
+            if account.storage.contains_key(index) {
+                keys.push(key);
+            } else {
+                report.missing_from_replay.push(key);
+            }
+        }
+    }
+    for (address, account) in &replayed.accounts {
+        let Some(slots) = listed.get(address) else {
+            report.unexpected_in_replay.push(FetchKey::Basic(*address));
+            continue;
+        };
+        report.unexpected_in_replay.extend(
+            account
+                .storage
+                .keys()
+                .filter(|index| !slots.contains(*index))
+                .map(|index| FetchKey::Storage(*address, *index)),
+        );
+    }
+    keys.extend(
+        replayed
+            .contracts
+            .iter()
+            .filter(|(_, bytecode)| !bytecode.is_empty())
+            .map(|(code_hash, _)| FetchKey::CodeByHash(*code_hash)),
+    );
+    keys.extend(
+        replayed
+            .block_hashes
+            .keys()
+            .filter_map(|number| u64::try_from(*number).ok())
+            .map(FetchKey::BlockHash),
+    );
+    report.mismatches = compare_keys(keys, replayed, live)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DbStats, FetchKind};
+
+    #[test]
+    fn ops_keep_fetch_order_and_skip_non_reads() {
+        let address = Address::with_last_byte(1);
+        let bytecode = Bytecode::new_raw([0x60, 0x01, 0x00].to_vec().into());
+        let code_hash = bytecode.hash_slow();
+        let info = AccountInfo {
+            nonce: 1,
+            ..Default::default()
+        };
+        let fetches = vec![
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(2),
+            },
+            NewFetch::Error {
+                kind: FetchKind::Basic,
+                address: Some(address),
+                index: None,
+                message: "backend unavailable".into(),
+            },
+            NewFetch::Basic {
+                address,
+                account_info: info.clone(),
+            },
+            NewFetch::CacheHit {
+                key: FetchKey::Basic(address),
+            },
+            NewFetch::BasicWrite {
+                address,
+                account_info: AccountInfo::default(),
+            },
+            NewFetch::StorageWrite {
+                address,
+                index: U256::from(1),
+                value: U256::ZERO,
+            },
+            NewFetch::BasicMissing {
+                address: Address::ZERO,
+            },
+            NewFetch::CodeByHash {
+                code_hash,
+                bytecode: bytecode.clone(),
+            },
+            NewFetch::BlockHash {
+                number: 1,
+                hash: B256::repeat_byte(1),
+            },
+            NewFetch::Summary {
+                stats: DbStats::default(),
+            },
+        ];
+
+        let ops: Vec<_> = fetches_to_ops(fetches).collect();
+        assert_eq!(
+            ops,
+            [
+                CacheOp::InsertStorage {
+                    address,
+                    index: U256::from(1),
+                    value: U256::from(2),
+                },
+                CacheOp::InsertAccount { address, info },
+                CacheOp::InsertMissingAccount {
+                    address: Address::ZERO,
+                },
+                CacheOp::InsertCode {
+                    code_hash,
+                    bytecode,
+                },
+                CacheOp::InsertBlockHash {
+                    number: 1,
+                    hash: B256::repeat_byte(1),
+                },
+            ]
+        );
+    }
+}
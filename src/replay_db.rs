@@ -0,0 +1,154 @@
+use revm::{
+    db::{AccountState, CacheDB, EmptyDB},
+    primitives::{Account, AccountInfo, Address, Bytecode, HashMap, B256, KECCAK_EMPTY, U256},
+    Database, DatabaseCommit, DatabaseRef,
+};
+
+use crate::{cache_db_from_fetches, FetchKey, NewFetch, ProxyDbError};
+
+/// Serves a recorded run back from memory, failing every lookup it has no answer for.
+///
+/// Unlike a `CacheDB` over a live db, a miss never falls through to anything else, so a
+/// replay that succeeds is guaranteed to be self-contained. Storage of accounts recorded
+/// as missing reads as zero; so does nothing else, which makes recordings taken with
+/// [`skip_zero_storage`](crate::RecordingDb::skip_zero_storage) unsuitable.
+#[derive(Debug, Clone)]
+pub struct ReplayDb {
+    cache: CacheDB<EmptyDB>,
+}
+
+impl ReplayDb {
+    pub fn new(cache: CacheDB<EmptyDB>) -> Self {
+        Self { cache }
+    }
+
+    /// Replays exactly the recorded `fetches`, see [`cache_db_from_fetches`].
+    pub fn from_fetches(fetches: impl IntoIterator<Item = NewFetch>) -> Result<Self, ProxyDbError> {
+        cache_db_from_fetches(fetches).map(Self::new)
+    }
+
+    pub fn cache(&self) -> &CacheDB<EmptyDB> {
+        &self.cache
+    }
+
+    pub fn into_cache(self) -> CacheDB<EmptyDB> {
+        self.cache
+    }
+}
+
+impl DatabaseRef for ReplayDb {
+    type Error = ProxyDbError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.cache
+            .accounts
+            .get(&address)
+            .map(|account| account.info())
+            .ok_or(ProxyDbError::NotCaptured(FetchKey::Basic(address)))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        if code_hash == KECCAK_EMPTY || code_hash.is_zero() {
+            return Ok(Bytecode::default());
+        }
+        self.cache
+            .contracts
+            .get(&code_hash)
+            .cloned()
+            .ok_or(ProxyDbError::NotCaptured(FetchKey::CodeByHash(code_hash)))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let account = self.cache.accounts.get(&address);
+        if let Some(value) = account.and_then(|account| account.storage.get(&index)) {
+            return Ok(*value);
+        }
+        match account.map(|account| &account.account_state) {
+            Some(AccountState::NotExisting | AccountState::StorageCleared) => Ok(U256::ZERO),
+            _ => Err(ProxyDbError::NotCaptured(FetchKey::Storage(address, index))),
+        }
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.cache
+            .block_hashes
+            .get(&U256::from(number))
+            .copied()
+            .ok_or(ProxyDbError::NotCaptured(FetchKey::BlockHash(number)))
+    }
+}
+
+impl Database for ReplayDb {
+    type Error = ProxyDbError;
+
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.basic_ref(address)
+    }
+
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.code_by_hash_ref(code_hash)
+    }
+
+    fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.storage_ref(address, index)
+    }
+
+    fn block_hash(&mut self, number: u64) -> Result<B256, Self::Error> {
+        self.block_hash_ref(number)
+    }
+}
+
+impl DatabaseCommit for ReplayDb {
+    /// Later reads see the committed state, like they would on the recorded run.
+    fn commit(&mut self, changes: HashMap<Address, Account>) {
+        self.cache.commit(changes)
+    }
+}
+
+/// An inner db for a `CacheDB` that is expected to answer everything itself, failing
+/// every lookup with the key that reached it, see [`assert_offline`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OfflineDb;
+
+impl DatabaseRef for OfflineDb {
+    type Error = ProxyDbError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Err(ProxyDbError::NotCaptured(FetchKey::Basic(address)))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        Err(ProxyDbError::NotCaptured(FetchKey::CodeByHash(code_hash)))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        Err(ProxyDbError::NotCaptured(FetchKey::Storage(address, index)))
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        Err(ProxyDbError::NotCaptured(FetchKey::BlockHash(number)))
+    }
+}
+
+/// Swaps the inner db of a warmed `cache_db` for an [`OfflineDb`], so a lookup the
+/// cache cannot answer fails with [`ProxyDbError::NotCaptured`] instead of silently
+/// reaching the inner db.
+///
+/// Unlike a [`ReplayDb`], the result is still a `CacheDB`, so it keeps every `CacheDB`
+/// behaviour and fits wherever one is expected.
+pub fn assert_offline<ExtDB>(cache_db: CacheDB<ExtDB>) -> CacheDB<OfflineDb> {
+    let CacheDB {
+        accounts,
+        contracts,
+        logs,
+        block_hashes,
+        ..
+    } = cache_db;
+    CacheDB {
+        accounts,
+        contracts,
+        logs,
+        block_hashes,
+        db: OfflineDb,
+    }
+}
@@ -0,0 +1,241 @@
+use std::{fmt, future::Future, time::Duration};
+
+use revm::{
+    primitives::{AccountInfo, Address, Bytecode, B256, U256},
+    DatabaseRef,
+};
+
+use crate::AsyncDatabaseRef;
+
+/// Wraps a flaky db, retrying failed lookups that `is_retryable` accepts.
+///
+/// Place it below a [`RecordingDb`](crate::RecordingDb) so only successful results get recorded.
+/// The delay between attempts starts at the configured backoff and doubles after every
+/// failed attempt. The sync impl sleeps the calling thread.
+pub struct RetryDb<ExtDB, F> {
+    db: ExtDB,
+    retries: u32,
+    backoff: Duration,
+    timeout: Option<Duration>,
+    is_retryable: F,
+}
+
+impl<ExtDB, F> RetryDb<ExtDB, F> {
+    /// Retries each lookup up to `retries` times, without any delay in between.
+    pub fn new(db: ExtDB, retries: u32, is_retryable: F) -> Self {
+        Self {
+            db,
+            retries,
+            backoff: Duration::ZERO,
+            timeout: None,
+            is_retryable,
+        }
+    }
+
+    /// Waits `backoff` before the first retry, doubling it before each next one.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fails async attempts taking longer than `timeout` with [`RetryError::Timeout`],
+    /// which is always retried. Sync lookups cannot be interrupted and ignore it.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn inner(&self) -> &ExtDB {
+        &self.db
+    }
+
+    pub fn inner_mut(&mut self) -> &mut ExtDB {
+        &mut self.db
+    }
+
+    pub fn into_inner(self) -> ExtDB {
+        self.db
+    }
+
+    fn retry<T, E>(&self, mut call: impl FnMut() -> Result<T, E>) -> Result<T, E>
+    where
+        F: Fn(&E) -> bool,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match call() {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt >= self.retries || !(self.is_retryable)(&err) => {
+                    return Err(err)
+                }
+                Err(_) => {}
+            }
+            attempt += 1;
+            std::thread::sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+
+    async fn retry_async<T, E, Fut>(
+        &self,
+        mut call: impl FnMut() -> Fut,
+    ) -> Result<T, RetryError<E>>
+    where
+        F: Fn(&E) -> bool,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            {
+                let err = match self.timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, call()).await {
+                        Ok(Ok(value)) => return Ok(value),
+                        Ok(Err(err)) => RetryError::Inner(err),
+                        Err(_) => RetryError::Timeout,
+                    },
+                    None => match call().await {
+                        Ok(value) => return Ok(value),
+                        Err(err) => RetryError::Inner(err),
+                    },
+                };
+                let retryable = match &err {
+                    RetryError::Inner(err) => (self.is_retryable)(err),
+                    RetryError::Timeout => true,
+                };
+                if attempt >= self.retries || !retryable {
+                    return Err(err);
+                }
+            }
+            attempt += 1;
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+}
+
+impl<ExtDB, F> DatabaseRef for RetryDb<ExtDB, F>
+where
+    ExtDB: DatabaseRef,
+    F: Fn(&ExtDB::Error) -> bool,
+{
+    type Error = ExtDB::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.retry(|| self.db.basic_ref(address))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.retry(|| self.db.code_by_hash_ref(code_hash))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.retry(|| self.db.storage_ref(address, index))
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.retry(|| self.db.block_hash_ref(number))
+    }
+}
+
+impl<ExtDB, F> AsyncDatabaseRef for RetryDb<ExtDB, F>
+where
+    ExtDB: AsyncDatabaseRef + Sync,
+    F: Fn(&ExtDB::Error) -> bool + Sync,
+{
+    type Error = RetryError<ExtDB::Error>;
+
+    async fn basic_async_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.retry_async(|| self.db.basic_async_ref(address)).await
+    }
+
+    async fn code_by_hash_async_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.retry_async(|| self.db.code_by_hash_async_ref(code_hash))
+            .await
+    }
+
+    async fn storage_async_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.retry_async(|| self.db.storage_async_ref(address, index))
+            .await
+    }
+
+    async fn block_hash_async_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.retry_async(|| self.db.block_hash_async_ref(number))
+            .await
+    }
+}
+
+/// Error of the async lookups of a [`RetryDb`], once it gave up.
+#[derive(Debug)]
+pub enum RetryError<E> {
+    /// The last error returned by the inner db.
+    Inner(E),
+    /// The last attempt exceeded the timeout set with [`RetryDb::with_timeout`].
+    Timeout,
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Inner(err) => err.fmt(f),
+            Self::Timeout => write!(f, "lookup timed out"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for RetryError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Inner(err) => Some(err),
+            Self::Timeout => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::{FlakyDb, MockDb, Unavailable};
+
+    #[test]
+    fn retries_until_the_lookup_succeeds() {
+        let db = RetryDb::new(FlakyDb::failing(2), 2, |_: &Unavailable| true)
+            .with_backoff(Duration::from_millis(1));
+        assert_eq!(db.basic_ref(Address::ZERO), Ok(None));
+        assert_eq!(db.inner().attempts(), 3);
+    }
+
+    #[test]
+    fn gives_up_with_the_last_error() {
+        let db = RetryDb::new(FlakyDb::failing(2), 1, |_: &Unavailable| true);
+        assert_eq!(db.basic_ref(Address::ZERO), Err(Unavailable { attempt: 1 }));
+    }
+
+    #[test]
+    fn does_not_retry_rejected_errors() {
+        let db = RetryDb::new(FlakyDb::failing(2), 5, |_: &Unavailable| false);
+        assert_eq!(db.basic_ref(Address::ZERO), Err(Unavailable { attempt: 0 }));
+        assert_eq!(db.inner().attempts(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn async_attempts_time_out() {
+        let mock = MockDb::new().with_delay(Duration::from_secs(10));
+        let db = RetryDb::new(mock, 2, |_: &std::convert::Infallible| false)
+            .with_timeout(Duration::from_secs(1));
+        let result = db.basic_async_ref(Address::ZERO).await;
+        assert!(matches!(result, Err(RetryError::Timeout)));
+        assert_eq!(db.inner().calls().basic_reads, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backoff_saturates_instead_of_overflowing() {
+        // Doubling this backoff twice overflows a `Duration`.
+        let db = RetryDb::new(FlakyDb::failing(100), 100, |_: &Unavailable| true)
+            .with_backoff(Duration::MAX / 2);
+        assert!(matches!(db.basic_async_ref(Address::ZERO).await, Ok(None)));
+        assert_eq!(db.inner().attempts(), 101);
+        assert_eq!(db.inner().inner().calls().basic_reads, 1);
+    }
+}
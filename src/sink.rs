@@ -0,0 +1,413 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::mpsc::{error::TrySendError, Sender, UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{NewFetch, SequencedFetch, TimedFetch};
+
+/// Destination for the fetches recorded by a [`RecordingDb`](crate::RecordingDb).
+pub trait FetchSink {
+    fn record(&self, fetch: NewFetch);
+
+    /// Whether fetches recorded from now on are lost, e.g. because the receiving end of
+    /// a channel was dropped. Sinks that cannot tell always report `false`.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+impl FetchSink for UnboundedSender<NewFetch> {
+    fn record(&self, fetch: NewFetch) {
+        let _ = self.send(fetch);
+    }
+
+    fn is_closed(&self) -> bool {
+        UnboundedSender::is_closed(self)
+    }
+}
+
+impl FetchSink for mpsc::Sender<NewFetch> {
+    fn record(&self, fetch: NewFetch) {
+        let _ = self.send(fetch);
+    }
+}
+
+impl FetchSink for Mutex<Vec<NewFetch>> {
+    fn record(&self, fetch: NewFetch) {
+        if let Ok(mut fetches) = self.lock() {
+            fetches.push(fetch);
+        }
+    }
+}
+
+/// A sink that discards every fetch.
+///
+/// It reports itself closed, so a proxy over it skips building events altogether and
+/// only forwards reads, keeping its stats and read cache if enabled.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl FetchSink for NoopSink {
+    #[inline]
+    fn record(&self, _fetch: NewFetch) {}
+
+    #[inline]
+    fn is_closed(&self) -> bool {
+        true
+    }
+}
+
+/// A single-threaded in-memory buffer of fetches, shared by its clones.
+#[derive(Clone, Debug, Default)]
+pub struct FetchBuffer {
+    fetches: Rc<RefCell<Vec<NewFetch>>>,
+}
+
+impl FetchBuffer {
+    /// Removes and returns every fetch buffered so far.
+    pub fn take(&self) -> Vec<NewFetch> {
+        self.fetches.take()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fetches.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fetches.borrow().is_empty()
+    }
+}
+
+impl FetchSink for FetchBuffer {
+    fn record(&self, fetch: NewFetch) {
+        self.fetches.borrow_mut().push(fetch);
+    }
+}
+
+impl<T: FetchSink + ?Sized> FetchSink for Arc<T> {
+    fn record(&self, fetch: NewFetch) {
+        (**self).record(fetch)
+    }
+
+    fn is_closed(&self) -> bool {
+        (**self).is_closed()
+    }
+}
+
+impl<T: FetchSink + ?Sized> FetchSink for Box<T> {
+    fn record(&self, fetch: NewFetch) {
+        (**self).record(fetch)
+    }
+
+    fn is_closed(&self) -> bool {
+        (**self).is_closed()
+    }
+}
+
+/// A sink chosen at runtime, see [`RecordingDb::with_dyn_sink`](crate::RecordingDb::with_dyn_sink).
+pub type DynSink = Arc<dyn FetchSink + Send + Sync>;
+
+/// Runs a closure synchronously for every recorded fetch, in call order.
+pub struct FetchCallback<F>(pub F);
+
+impl<F: Fn(&NewFetch)> FetchSink for FetchCallback<F> {
+    fn record(&self, fetch: NewFetch) {
+        (self.0)(&fetch)
+    }
+}
+
+/// What a bounded sender does with a fetch when its channel is full.
+///
+/// `Drop` and `Error` never stall the EVM but lose events under load, `Block`
+/// keeps every event at the cost of tying execution speed to the consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Silently discard the fetch.
+    Drop,
+    /// Wait for the consumer to make room. On a current-thread runtime, where waiting
+    /// would stall the only thread able to drain the channel, the fetch is discarded
+    /// and flagged as with `Error` instead.
+    Block,
+    /// Discard the fetch and flag the overflow, see [`BoundedSender::overflowed`].
+    Error,
+}
+
+/// A bounded tokio channel paired with the [`BackpressurePolicy`] applied when it is full.
+///
+/// Clones share the overflow flag.
+#[derive(Clone)]
+pub struct BoundedSender {
+    sender: Sender<NewFetch>,
+    policy: BackpressurePolicy,
+    overflowed: Arc<AtomicBool>,
+}
+
+impl BoundedSender {
+    pub fn new(sender: Sender<NewFetch>, policy: BackpressurePolicy) -> Self {
+        Self {
+            sender,
+            policy,
+            overflowed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn policy(&self) -> BackpressurePolicy {
+        self.policy
+    }
+
+    /// Whether a fetch has been rejected under [`BackpressurePolicy::Error`], or under
+    /// [`BackpressurePolicy::Block`] on a current-thread runtime.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+}
+
+impl FetchSink for BoundedSender {
+    fn record(&self, fetch: NewFetch) {
+        match self.sender.try_send(fetch) {
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(fetch)) => match self.policy {
+                BackpressurePolicy::Drop => {}
+                BackpressurePolicy::Block => match Handle::try_current() {
+                    Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::CurrentThread => {
+                        self.overflowed.store(true, Ordering::Relaxed)
+                    }
+                    Ok(handle) => tokio::task::block_in_place(|| {
+                        let _ = handle.block_on(self.sender.send(fetch));
+                    }),
+                    Err(_) => {
+                        let _ = self.sender.blocking_send(fetch);
+                    }
+                },
+                BackpressurePolicy::Error => self.overflowed.store(true, Ordering::Relaxed),
+            },
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+/// An unbounded channel of [`TimedFetch`]es, stamped relative to a start marker.
+#[derive(Clone)]
+pub struct TimedSender {
+    start: Instant,
+    sender: UnboundedSender<TimedFetch>,
+}
+
+impl TimedSender {
+    pub fn new(start: Instant, sender: UnboundedSender<TimedFetch>) -> Self {
+        Self { start, sender }
+    }
+
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+}
+
+impl FetchSink for TimedSender {
+    fn record(&self, fetch: NewFetch) {
+        let _ = self.sender.send(TimedFetch {
+            at: self.start.elapsed(),
+            fetch,
+        });
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+/// An unbounded channel of [`SequencedFetch`]es.
+///
+/// Numbering and sending happen under one lock, so the receiver sees the sequence
+/// numbers strictly increasing and without gaps, also with concurrent clones.
+#[derive(Clone)]
+pub struct SequencedSender {
+    next: Arc<Mutex<u64>>,
+    sender: UnboundedSender<SequencedFetch>,
+}
+
+impl SequencedSender {
+    pub fn new(sender: UnboundedSender<SequencedFetch>) -> Self {
+        Self {
+            next: Arc::new(Mutex::new(0)),
+            sender,
+        }
+    }
+
+    /// The number the next recorded fetch gets, i.e. the number recorded so far.
+    pub fn next_seq(&self) -> u64 {
+        self.next.lock().map_or(0, |next| *next)
+    }
+}
+
+impl FetchSink for SequencedSender {
+    fn record(&self, fetch: NewFetch) {
+        let Ok(mut next) = self.next.lock() else {
+            return;
+        };
+        if self
+            .sender
+            .send(SequencedFetch { seq: *next, fetch })
+            .is_ok()
+        {
+            *next += 1;
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+/// Sends every fetch to several unbounded channels.
+///
+/// Senders whose receiver was dropped are pruned on the next fetch. Clones share the
+/// same list of senders.
+#[derive(Clone, Default)]
+pub struct FanOutSender {
+    senders: Arc<Mutex<Vec<UnboundedSender<NewFetch>>>>,
+}
+
+impl FanOutSender {
+    pub fn new(senders: impl IntoIterator<Item = UnboundedSender<NewFetch>>) -> Self {
+        Self {
+            senders: Arc::new(Mutex::new(senders.into_iter().collect())),
+        }
+    }
+
+    pub fn push(&self, sender: UnboundedSender<NewFetch>) {
+        if let Ok(mut senders) = self.senders.lock() {
+            senders.push(sender);
+        }
+    }
+
+    /// Number of senders left, including closed ones not pruned yet.
+    pub fn len(&self) -> usize {
+        self.senders.lock().map_or(0, |senders| senders.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl FetchSink for FanOutSender {
+    fn record(&self, fetch: NewFetch) {
+        let Ok(mut senders) = self.senders.lock() else {
+            return;
+        };
+        senders.retain(|sender| !sender.is_closed());
+        if let Some((last, rest)) = senders.split_last() {
+            for sender in rest {
+                let _ = sender.send(fetch.clone());
+            }
+            let _ = last.send(fetch);
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.senders.lock().map_or(true, |senders| {
+            senders.iter().all(|sender| sender.is_closed())
+        })
+    }
+}
+
+/// Sends fetches over an unbounded channel in batches of a fixed size.
+///
+/// Larger batches cut the per-fetch channel overhead, but a fetch only reaches the
+/// consumer once its batch fills up, [`BatchSender::flush`] is called or the sender and
+/// all of its clones are dropped. Clones fill the same batch.
+#[derive(Clone)]
+pub struct BatchSender {
+    batch_size: usize,
+    batch: Arc<Batch>,
+}
+
+/// The state shared by the clones of a [`BatchSender`], flushed once the last one drops.
+struct Batch {
+    sender: UnboundedSender<Vec<NewFetch>>,
+    buffer: Mutex<Vec<NewFetch>>,
+}
+
+impl Batch {
+    fn flush(&self) {
+        let Ok(mut buffer) = self.buffer.lock() else {
+            return;
+        };
+        if !buffer.is_empty() {
+            let _ = self.sender.send(std::mem::take(&mut *buffer));
+        }
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl BatchSender {
+    /// A `batch_size` of zero is treated as one.
+    pub fn new(sender: UnboundedSender<Vec<NewFetch>>, batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        Self {
+            batch_size,
+            batch: Arc::new(Batch {
+                sender,
+                buffer: Mutex::new(Vec::with_capacity(batch_size)),
+            }),
+        }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Sends the fetches buffered so far as a partial batch, if there are any.
+    pub fn flush(&self) {
+        self.batch.flush();
+    }
+}
+
+impl FetchSink for BatchSender {
+    fn record(&self, fetch: NewFetch) {
+        let Ok(mut buffer) = self.batch.buffer.lock() else {
+            return;
+        };
+        buffer.push(fetch);
+        if buffer.len() >= self.batch_size {
+            let batch = std::mem::replace(&mut *buffer, Vec::with_capacity(self.batch_size));
+            let _ = self.batch.sender.send(batch);
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.batch.sender.is_closed()
+    }
+}
+
+/// Collects every fetch left in `receiver` until its channel closes.
+///
+/// To capture a run completely, first [`flush`](crate::RecordingDb::flush) or drop the proxy
+/// and all of its clones, then drain the receiver. Draining while a sender is still alive
+/// waits for it.
+pub async fn drain_receiver(mut receiver: UnboundedReceiver<NewFetch>) -> Vec<NewFetch> {
+    let mut fetches = Vec::new();
+    while let Some(fetch) = receiver.recv().await {
+        fetches.push(fetch);
+    }
+    fetches
+}
@@ -0,0 +1,129 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+use revm::primitives::{Address, HashSet, B256, U256};
+#[cfg(feature = "persistence")]
+use serde::{Deserialize, Serialize};
+
+/// Number of lookups a [`RecordingDb`](crate::RecordingDb) forwarded to its inner db.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct DbStats {
+    pub basic_reads: u64,
+    pub storage_reads: u64,
+    pub code_reads: u64,
+    pub block_hash_reads: u64,
+    /// Distinct keys per lookup kind, only tracked when enabled with
+    /// [`RecordingDb::with_unique_stats`](crate::RecordingDb::with_unique_stats).
+    pub unique: Option<UniqueStats>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "persistence", derive(Serialize, Deserialize))]
+pub struct UniqueStats {
+    pub accounts: usize,
+    pub storage_slots: usize,
+    pub code_hashes: usize,
+    pub block_numbers: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    basic_reads: AtomicU64,
+    storage_reads: AtomicU64,
+    code_reads: AtomicU64,
+    block_hash_reads: AtomicU64,
+    unique: Option<UniqueKeys>,
+}
+
+#[derive(Debug, Default)]
+struct UniqueKeys {
+    accounts: Mutex<HashSet<Address>>,
+    storage_slots: Mutex<HashSet<(Address, U256)>>,
+    code_hashes: Mutex<HashSet<B256>>,
+    block_numbers: Mutex<HashSet<u64>>,
+}
+
+impl Stats {
+    pub(crate) fn with_unique() -> Self {
+        Self {
+            unique: Some(UniqueKeys::default()),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn basic(&self, address: Address) {
+        self.basic_reads.fetch_add(1, Ordering::Relaxed);
+        if let Some(unique) = &self.unique {
+            insert(&unique.accounts, address);
+        }
+    }
+
+    pub(crate) fn storage(&self, address: Address, index: U256) {
+        self.storage_reads.fetch_add(1, Ordering::Relaxed);
+        if let Some(unique) = &self.unique {
+            insert(&unique.storage_slots, (address, index));
+        }
+    }
+
+    pub(crate) fn code(&self, code_hash: B256) {
+        self.code_reads.fetch_add(1, Ordering::Relaxed);
+        if let Some(unique) = &self.unique {
+            insert(&unique.code_hashes, code_hash);
+        }
+    }
+
+    pub(crate) fn block_hash(&self, number: u64) {
+        self.block_hash_reads.fetch_add(1, Ordering::Relaxed);
+        if let Some(unique) = &self.unique {
+            insert(&unique.block_numbers, number);
+        }
+    }
+
+    /// Zeroes the counters and forgets the distinct keys seen so far.
+    pub(crate) fn reset(&self) {
+        self.basic_reads.store(0, Ordering::Relaxed);
+        self.storage_reads.store(0, Ordering::Relaxed);
+        self.code_reads.store(0, Ordering::Relaxed);
+        self.block_hash_reads.store(0, Ordering::Relaxed);
+        if let Some(unique) = &self.unique {
+            clear(&unique.accounts);
+            clear(&unique.storage_slots);
+            clear(&unique.code_hashes);
+            clear(&unique.block_numbers);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> DbStats {
+        DbStats {
+            basic_reads: self.basic_reads.load(Ordering::Relaxed),
+            storage_reads: self.storage_reads.load(Ordering::Relaxed),
+            code_reads: self.code_reads.load(Ordering::Relaxed),
+            block_hash_reads: self.block_hash_reads.load(Ordering::Relaxed),
+            unique: self.unique.as_ref().map(|unique| UniqueStats {
+                accounts: len(&unique.accounts),
+                storage_slots: len(&unique.storage_slots),
+                code_hashes: len(&unique.code_hashes),
+                block_numbers: len(&unique.block_numbers),
+            }),
+        }
+    }
+}
+
+fn insert<K: Eq + std::hash::Hash>(set: &Mutex<HashSet<K>>, key: K) {
+    if let Ok(mut set) = set.lock() {
+        set.insert(key);
+    }
+}
+
+fn clear<K>(set: &Mutex<HashSet<K>>) {
+    if let Ok(mut set) = set.lock() {
+        set.clear();
+    }
+}
+
+fn len<K>(set: &Mutex<HashSet<K>>) -> usize {
+    set.lock().map(|set| set.len()).unwrap_or_default()
+}
@@ -0,0 +1,259 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+use revm::{
+    db::{CacheDB, EmptyDB},
+    primitives::{
+        address, b256, AccountInfo, Address, Bytecode, Bytes, EVMError, ExecutionResult, Output,
+        ResultAndState, TxKind, U256,
+    },
+    DatabaseRef, Evm,
+};
+use revm_proxy_db::{
+    apply_fetches_to_cache_db, assert_offline, cache_db_from_fetches, drain_receiver, FetchKey,
+    MockDb, NewFetch, ProxyDbError, RecordingDb, ReplayDb,
+};
+
+const CALLER: Address = address!("00000000000000000000000000000000000000ca");
+const CONTRACT: Address = address!("00000000000000000000000000000000000000c0");
+
+fn contract(code: &'static [u8]) -> AccountInfo {
+    AccountInfo::from_bytecode(Bytecode::new_raw(Bytes::from_static(code)))
+}
+
+/// Calls `CONTRACT` from `CALLER` at block 100.
+fn transact<DB: DatabaseRef>(db: DB) -> Result<ResultAndState, EVMError<DB::Error>> {
+    let mut evm = Evm::builder()
+        .with_ref_db(db)
+        .modify_block_env(|block| block.number = U256::from(100))
+        .modify_tx_env(|tx| {
+            tx.caller = CALLER;
+            tx.transact_to = TxKind::Call(CONTRACT);
+            tx.gas_limit = 1_000_000;
+            tx.gas_price = U256::ZERO;
+        })
+        .build();
+    evm.transact()
+}
+
+/// Calls `CONTRACT` and returns what it returned.
+fn call<DB: DatabaseRef>(db: DB) -> Bytes
+where
+    DB::Error: std::fmt::Debug,
+{
+    match transact(db).unwrap().result {
+        ExecutionResult::Success {
+            output: Output::Call(output),
+            ..
+        } => output,
+        result => panic!("call failed: {result:?}"),
+    }
+}
+
+#[tokio::test]
+async fn blockhash_opcode_is_recorded() {
+    let hash = b256!("1111111111111111111111111111111111111111111111111111111111111111");
+    // BLOCKHASH(90), returned as a 32-byte word.
+    let code = &[
+        0x60, 0x5a, 0x40, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+    ];
+    let mock = MockDb::new()
+        .with_account(CONTRACT, contract(code))
+        .with_block_hash(90, hash);
+    let (db, receiver) = RecordingDb::new_with_channel(mock);
+
+    assert_eq!(call(&db)[..], hash[..]);
+    drop(db);
+    let block_hashes: Vec<_> = drain_receiver(receiver)
+        .await
+        .into_iter()
+        .filter(|fetch| matches!(fetch, NewFetch::BlockHash { .. }))
+        .collect();
+    assert_eq!(block_hashes, [NewFetch::BlockHash { number: 90, hash }]);
+}
+
+#[tokio::test]
+async fn callback_sees_every_fetch_of_a_run() {
+    // SLOAD(1) + SLOAD(2).
+    let code = &[0x60, 0x01, 0x54, 0x60, 0x02, 0x54, 0x01, 0x00];
+    let mock = || {
+        MockDb::new()
+            .with_account(CONTRACT, contract(code))
+            .with_storage(CONTRACT, U256::from(1), U256::from(7))
+    };
+    let (db, receiver) = RecordingDb::new_with_channel(mock());
+    call(&db);
+    drop(db);
+    let sent = drain_receiver(receiver).await;
+
+    let count = AtomicUsize::new(0);
+    let seen = Mutex::new(Vec::new());
+    let db = RecordingDb::new(mock()).with_callback(|fetch| {
+        count.fetch_add(1, Ordering::Relaxed);
+        seen.lock().unwrap().push(fetch.clone());
+    });
+    call(&db);
+    drop(db);
+
+    assert_eq!(count.into_inner(), sent.len());
+    assert_eq!(seen.into_inner().unwrap(), sent);
+}
+
+#[tokio::test]
+async fn skip_zero_storage_drops_empty_slots() {
+    // SLOAD(1) + SLOAD(2) + SLOAD(3), only slot 1 being set.
+    let code = &[
+        0x60, 0x01, 0x54, 0x60, 0x02, 0x54, 0x60, 0x03, 0x54, 0x01, 0x01, 0x00,
+    ];
+    for (skip_zero_storage, expected) in [(false, 3), (true, 1)] {
+        let mock = MockDb::new()
+            .with_account(CONTRACT, contract(code))
+            .with_storage(CONTRACT, U256::from(1), U256::from(7));
+        let (mut db, receiver) = RecordingDb::new_with_channel(mock);
+        if skip_zero_storage {
+            db = db.skip_zero_storage();
+        }
+        call(&db);
+        drop(db);
+        let storage_reads = drain_receiver(receiver)
+            .await
+            .into_iter()
+            .filter(|fetch| matches!(fetch, NewFetch::Storage { .. }))
+            .count();
+        assert_eq!(storage_reads, expected);
+    }
+}
+
+#[tokio::test]
+async fn applied_fetches_match_the_recorded_cache() {
+    // SSTORE(3, SLOAD(1) + SLOAD(2)).
+    let code = &[
+        0x60, 0x01, 0x54, 0x60, 0x02, 0x54, 0x01, 0x60, 0x03, 0x55, 0x00,
+    ];
+    let mut live = CacheDB::new(EmptyDB::new());
+    live.insert_account_info(CONTRACT, contract(code));
+    live.insert_account_info(
+        CALLER,
+        AccountInfo {
+            balance: U256::from(1_000),
+            ..Default::default()
+        },
+    );
+    for (index, value) in [(1, 7), (2, 8), (9, 9)] {
+        live.insert_account_storage(CONTRACT, U256::from(index), U256::from(value))
+            .unwrap();
+    }
+    let (db, receiver) = RecordingDb::new_with_channel(&live);
+    call(&db);
+    drop(db);
+
+    let mut replayed = CacheDB::new(EmptyDB::new());
+    apply_fetches_to_cache_db(&mut replayed, drain_receiver(receiver).await).unwrap();
+    assert!(replayed.accounts.contains_key(&CALLER));
+    for (address, account) in &replayed.accounts {
+        assert_eq!(account.info(), live.basic_ref(*address).unwrap());
+        for (index, value) in &account.storage {
+            assert_eq!(*value, live.storage_ref(*address, *index).unwrap());
+        }
+    }
+    let storage = &replayed.accounts[&CONTRACT].storage;
+    assert_eq!(storage.len(), 3);
+    assert_eq!(storage[&U256::from(1)], U256::from(7));
+    assert_eq!(replayed.contracts, live.contracts);
+}
+
+#[tokio::test]
+async fn replayed_run_matches_the_recorded_one() {
+    // SSTORE(0, BALANCE(CALLER) + SLOAD(1) + BLOCKHASH(99)), returning the sum.
+    let code = Bytecode::new_raw(Bytes::from_static(&[
+        0x33, 0x31, 0x60, 0x01, 0x54, 0x01, 0x60, 0x63, 0x40, 0x01, 0x80, 0x60, 0x00, 0x55, 0x60,
+        0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+    ]));
+    // Leave the code out of the account, so it is fetched by hash.
+    let contract = AccountInfo {
+        code: None,
+        ..AccountInfo::from_bytecode(code.clone())
+    };
+    let caller = AccountInfo {
+        balance: U256::from(1_000),
+        ..Default::default()
+    };
+    let mock = MockDb::new()
+        .with_account(CALLER, caller)
+        .with_account(CONTRACT, contract)
+        .with_code(code)
+        .with_storage(CONTRACT, U256::from(1), U256::from(7))
+        .with_block_hash(
+            99,
+            b256!("0000000000000000000000000000000000000000000000000000000000000063"),
+        );
+    let (db, receiver) = RecordingDb::new_with_channel(mock);
+    let recorded = transact(&db).unwrap();
+    drop(db);
+    let fetches = drain_receiver(receiver).await;
+    assert!(fetches
+        .iter()
+        .any(|fetch| matches!(fetch, NewFetch::CodeByHash { .. })));
+
+    let replayed = transact(ReplayDb::new(cache_db_from_fetches(fetches).unwrap())).unwrap();
+    assert!(replayed.result.is_success());
+    assert_eq!(
+        replayed.result.output().unwrap()[..],
+        U256::from(1_000 + 7 + 99).to_be_bytes::<32>()
+    );
+    assert_eq!(replayed, recorded);
+}
+
+#[test]
+fn offline_cache_reports_the_missing_slot() {
+    // SLOAD(1), returned as a 32-byte word.
+    let code = &[
+        0x60, 0x01, 0x54, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+    ];
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    cache_db.insert_account_info(CALLER, AccountInfo::default());
+    cache_db.insert_account_info(CONTRACT, contract(code));
+    let offline = assert_offline(cache_db);
+
+    let missing = FetchKey::Storage(CONTRACT, U256::from(1));
+    let result = transact(&offline);
+    assert!(
+        matches!(&result, Err(EVMError::Database(ProxyDbError::NotCaptured(key))) if *key == missing),
+        "{result:?}"
+    );
+}
+
+#[tokio::test]
+async fn evm_drives_the_mutable_database_impl() {
+    // SLOAD(1), returned as a 32-byte word.
+    let code = &[
+        0x60, 0x01, 0x54, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+    ];
+    let mut live = CacheDB::new(EmptyDB::new());
+    live.insert_account_info(CONTRACT, contract(code));
+    live.insert_account_storage(CONTRACT, U256::from(1), U256::from(7))
+        .unwrap();
+    let (db, receiver) = RecordingDb::new_with_channel(live);
+
+    let mut evm = Evm::builder()
+        .with_db(db)
+        .modify_tx_env(|tx| {
+            tx.caller = CALLER;
+            tx.transact_to = TxKind::Call(CONTRACT);
+            tx.gas_price = U256::ZERO;
+        })
+        .build();
+    let output = evm.transact().unwrap().result.into_output().unwrap();
+    assert_eq!(output[..], U256::from(7).to_be_bytes::<32>());
+    drop(evm);
+
+    let fetches = drain_receiver(receiver).await;
+    assert!(fetches.contains(&NewFetch::Storage {
+        address: CONTRACT,
+        index: U256::from(1),
+        value: U256::from(7),
+    }));
+    assert!(fetches.contains(&NewFetch::BasicMissing { address: CALLER }));
+}
@@ -0,0 +1,112 @@
+#![cfg(feature = "alloy")]
+
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use alloy_json_rpc::{RequestPacket, ResponsePacket};
+use alloy_provider::{network::Ethereum, RootProvider};
+use alloy_rpc_client::RpcClient;
+use alloy_transport::{TransportError, TransportFut};
+use revm::{
+    primitives::{address, Address, Bytecode, Bytes, U256},
+    DatabaseRef,
+};
+use revm_proxy_db::{drain_receiver, fork_at_block, NewFetch};
+use serde_json::{json, Value};
+use tower::Service;
+
+const ACCOUNT: Address = address!("00000000000000000000000000000000000000aa");
+const CODE: &[u8] = &[0x60, 0x01, 0x00];
+
+/// Answers the lookups of `AlloyDB` with a fixed state, keeping the block each request
+/// was pinned to.
+#[derive(Clone, Default)]
+struct MockTransport {
+    blocks: Arc<Mutex<Vec<(String, Value)>>>,
+}
+
+impl MockTransport {
+    fn respond(&self, request: Value) -> Value {
+        let method = request["method"].as_str().unwrap().to_owned();
+        let params = request["params"].as_array().unwrap();
+        self.blocks
+            .lock()
+            .unwrap()
+            .push((method.clone(), params.last().unwrap().clone()));
+        let result = match method.as_str() {
+            "eth_getBalance" => json!("0x64"),
+            "eth_getTransactionCount" => json!("0x2"),
+            "eth_getCode" => json!(Bytes::from_static(CODE)),
+            "eth_getStorageAt" => json!("0x2a"),
+            method => panic!("unexpected {method}"),
+        };
+        json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+    }
+}
+
+impl Service<RequestPacket> for MockTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let response = match serde_json::to_value(&request).unwrap() {
+            Value::Array(batch) => batch
+                .into_iter()
+                .map(|request| self.respond(request))
+                .collect(),
+            request => self.respond(request),
+        };
+        Box::pin(async move { Ok(serde_json::from_value(response).unwrap()) })
+    }
+}
+
+fn provider(transport: MockTransport) -> RootProvider<MockTransport, Ethereum> {
+    RootProvider::new(RpcClient::new(transport, true))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn fork_reads_through_alloy_db_at_the_pinned_block() {
+    let transport = MockTransport::default();
+    let (fork, receiver) = fork_at_block(provider(transport.clone()), 123).unwrap();
+
+    let info = fork.basic_ref(ACCOUNT).unwrap().unwrap();
+    let bytecode = Bytecode::new_raw(Bytes::from_static(CODE));
+    assert_eq!((info.balance, info.nonce), (U256::from(100), 2));
+    assert_eq!(info.code_hash, bytecode.hash_slow());
+    assert_eq!(
+        fork.storage_ref(ACCOUNT, U256::from(1)).unwrap(),
+        U256::from(42)
+    );
+
+    let blocks = transport.blocks.lock().unwrap().clone();
+    assert_eq!(blocks.len(), 4);
+    assert!(blocks.iter().all(|(_, block)| *block == json!("0x7b")));
+    drop(fork);
+    let fetches = drain_receiver(receiver).await;
+    assert_eq!(
+        fetches,
+        [
+            NewFetch::Basic {
+                address: ACCOUNT,
+                account_info: info
+            },
+            NewFetch::Storage {
+                address: ACCOUNT,
+                index: U256::from(1),
+                value: U256::from(42)
+            },
+        ]
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn fork_needs_a_multi_threaded_runtime() {
+    assert!(fork_at_block(provider(MockTransport::default()), 123).is_none());
+}
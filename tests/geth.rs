@@ -0,0 +1,188 @@
+#![cfg(feature = "persistence")]
+
+use std::str::FromStr;
+
+use revm::{
+    db::{CacheDB, DbAccount, EmptyDB},
+    primitives::{AccountInfo, Address, Bytecode, Bytes, ExecutionResult, Output, TxKind, U256},
+    DatabaseRef, Evm,
+};
+use revm_proxy_db::{
+    apply_fetches_to_cache_db, cache_db_fingerprint, cache_db_from_geth_dump, cache_db_subset,
+    cache_db_to_genesis_alloc, group_fetches_by_account, merge_cache_dbs, NewFetch,
+};
+
+const ACCOUNT: &str = "0x00000000000000000000000000000000000000aa";
+
+/// Encodings of the same slot: minimal, zero-padded to a word and decimal where it fits.
+fn encodings() -> Vec<(U256, [&'static str; 3])> {
+    let max = "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    let below_max = "0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffe";
+    vec![
+        (
+            U256::ZERO,
+            [
+                "0x0",
+                "0x0000000000000000000000000000000000000000000000000000000000000000",
+                "0",
+            ],
+        ),
+        (
+            U256::from(1),
+            [
+                "0x1",
+                "0x0000000000000000000000000000000000000000000000000000000000000001",
+                "1",
+            ],
+        ),
+        (
+            U256::from(0x100),
+            [
+                "0x100",
+                "0x0000000000000000000000000000000000000000000000000000000000000100",
+                "256",
+            ],
+        ),
+        (U256::MAX - U256::from(1), [below_max, below_max, below_max]),
+        (
+            U256::MAX,
+            [
+                max,
+                "0x00ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                max,
+            ],
+        ),
+    ]
+}
+
+fn dump(encoding: usize) -> CacheDB<EmptyDB> {
+    let storage: Vec<_> = encodings()
+        .into_iter()
+        .map(|(_, keys)| format!(r#""{}": "0x{:x}""#, keys[encoding], encoding + 1))
+        .collect();
+    let dump = format!(
+        r#"{{ "{ACCOUNT}": {{ "balance": "0x1", "storage": {{ {} }} }} }}"#,
+        storage.join(", ")
+    );
+    cache_db_from_geth_dump(dump.as_bytes()).unwrap()
+}
+
+fn assert_one_entry_per_slot<'a>(slots: impl IntoIterator<Item = &'a U256>) {
+    let mut slots: Vec<_> = slots.into_iter().copied().collect();
+    slots.sort();
+    let expected: Vec<_> = encodings().into_iter().map(|(slot, _)| slot).collect();
+    assert_eq!(slots, expected);
+}
+
+#[test]
+fn slot_encodings_collapse_in_geth_imports() {
+    let address = Address::from_str(ACCOUNT).unwrap();
+    for encoding in 0..3 {
+        assert_one_entry_per_slot(dump(encoding).accounts[&address].storage.keys());
+    }
+
+    let mut merged = dump(0);
+    merge_cache_dbs(&mut merged, &dump(1));
+    merge_cache_dbs(&mut merged, &dump(2));
+    let storage = &merged.accounts[&address].storage;
+    assert_one_entry_per_slot(storage.keys());
+    assert!(storage.values().all(|value| *value == U256::from(3)));
+}
+
+#[test]
+fn slot_encodings_collapse_in_fetches() {
+    let address = Address::from_str(ACCOUNT).unwrap();
+    let fetches: Vec<_> = encodings()
+        .into_iter()
+        .flat_map(|(_, keys)| keys)
+        .enumerate()
+        .map(|(i, key)| NewFetch::Storage {
+            address,
+            index: U256::from_str(key).unwrap(),
+            value: U256::from(i),
+        })
+        .collect();
+
+    let grouped = group_fetches_by_account(fetches.clone());
+    assert_one_entry_per_slot(grouped[&address].storage.keys());
+
+    let mut cache_db = dump(1);
+    apply_fetches_to_cache_db(&mut cache_db, fetches).unwrap();
+    assert_one_entry_per_slot(cache_db.accounts[&address].storage.keys());
+}
+
+#[test]
+fn evm_reads_storage_from_a_geth_dump() {
+    // SLOAD(0), then return it as a word.
+    let code = "0x60005460005260206000f3";
+    let contract = Address::from_str(ACCOUNT).unwrap();
+    let dump = format!(
+        r#"{{ "accounts": {{ "{ACCOUNT}": {{ "code": "{code}", "storage": {{ "0x0": "0x2a" }} }} }} }}"#
+    );
+    let cache_db = cache_db_from_geth_dump(dump.as_bytes()).unwrap();
+    let info = cache_db.basic_ref(contract).unwrap().unwrap();
+    assert_eq!(
+        cache_db
+            .code_by_hash_ref(info.code_hash)
+            .unwrap()
+            .original_bytes(),
+        code.parse::<Bytes>().unwrap()
+    );
+
+    let mut evm = Evm::builder()
+        .with_ref_db(&cache_db)
+        .modify_tx_env(|tx| {
+            tx.caller = Address::with_last_byte(0xca);
+            tx.transact_to = TxKind::Call(contract);
+            tx.gas_price = U256::ZERO;
+        })
+        .build();
+    match evm.transact().unwrap().result {
+        ExecutionResult::Success {
+            output: Output::Call(output),
+            ..
+        } => assert_eq!(U256::from_be_slice(&output), U256::from(0x2a)),
+        result => panic!("call failed: {result:?}"),
+    }
+}
+
+#[test]
+fn genesis_alloc_round_trips_through_the_dump_loader() {
+    let [holder, contract, missing] = [1, 2, 3].map(Address::with_last_byte);
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    cache_db.insert_account_info(
+        holder,
+        AccountInfo {
+            balance: U256::from(0x10),
+            nonce: 2,
+            ..Default::default()
+        },
+    );
+    cache_db
+        .insert_account_storage(holder, U256::from(1), U256::from(0xff))
+        .unwrap();
+    cache_db.insert_account_info(contract, AccountInfo::from_bytecode(bytecode));
+    cache_db
+        .accounts
+        .insert(missing, DbAccount::new_not_existing());
+
+    let alloc = cache_db_to_genesis_alloc(&cache_db);
+    let entry = |address: Address| &alloc[format!("{address:#x}")];
+    assert_eq!(entry(holder)["balance"], "0x10");
+    assert_eq!(entry(holder)["nonce"], "0x2");
+    assert_eq!(
+        entry(holder)["storage"][format!("0x{:064x}", 1)],
+        format!("0x{:064x}", 0xff)
+    );
+    assert_eq!(entry(contract)["code"], "0x600100");
+    assert!(entry(contract).get("storage").is_none());
+    assert!(alloc.get(format!("{missing:#x}")).is_none());
+
+    let loaded = cache_db_from_geth_dump(alloc.to_string().as_bytes()).unwrap();
+    assert_eq!(loaded.accounts.len(), 2);
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db_subset(&cache_db, &[holder, contract]))
+    );
+}
@@ -0,0 +1,165 @@
+#![cfg(feature = "persistence")]
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use revm::primitives::{Address, U256};
+use revm_proxy_db::{
+    load_fetches_from_ndjson, spawn_ndjson_writer, spawn_ndjson_writer_compressed,
+    spawn_ndjson_writer_with_interval, NewFetch,
+};
+use tokio::{io::AsyncWrite, sync::mpsc::unbounded_channel};
+
+const SECOND: Duration = Duration::from_secs(1);
+
+fn fetches() -> Vec<NewFetch> {
+    (0..6)
+        .map(|byte| NewFetch::Storage {
+            address: Address::with_last_byte(byte),
+            index: U256::from(byte),
+            value: U256::from(byte) + U256::from(1),
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn compressed_capture_streams_back() {
+    let fetches = fetches();
+    let (sender, receiver) = unbounded_channel();
+    let writer = spawn_ndjson_writer_compressed(receiver, Vec::new());
+    for burst in fetches.chunks(2) {
+        for fetch in burst {
+            sender.send(fetch.clone()).unwrap();
+        }
+        tokio::task::yield_now().await;
+    }
+    drop(sender);
+    let bytes = writer.await.unwrap().unwrap();
+
+    assert!(bytes.starts_with(&[0x1f, 0x8b]));
+    let loaded: Vec<_> = load_fetches_from_ndjson(bytes.as_slice())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(loaded, fetches);
+}
+
+#[tokio::test]
+async fn plain_capture_streams_back() {
+    let fetches = fetches();
+    let (sender, receiver) = unbounded_channel();
+    let writer = spawn_ndjson_writer(receiver, Vec::new());
+    for fetch in &fetches {
+        sender.send(fetch.clone()).unwrap();
+    }
+    drop(sender);
+    let bytes = writer.await.unwrap().unwrap();
+
+    assert_eq!(
+        bytes.iter().filter(|byte| **byte == b'\n').count(),
+        fetches.len()
+    );
+    let loaded: Vec<_> = load_fetches_from_ndjson(bytes.as_slice())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(loaded, fetches);
+}
+
+#[tokio::test]
+async fn truncated_final_line_yields_the_complete_ones() {
+    let fetches = fetches();
+    let (sender, receiver) = unbounded_channel();
+    let writer = spawn_ndjson_writer(receiver, Vec::new());
+    for fetch in &fetches {
+        sender.send(fetch.clone()).unwrap();
+    }
+    drop(sender);
+    let mut bytes = writer.await.unwrap().unwrap();
+    bytes.truncate(bytes.len() - 10);
+
+    let loaded: Vec<_> = load_fetches_from_ndjson(bytes.as_slice()).collect();
+    assert_eq!(loaded.len(), fetches.len());
+    let (last, complete) = loaded.split_last().unwrap();
+    assert!(last.is_err());
+    for (loaded, fetch) in complete.iter().zip(&fetches) {
+        assert_eq!(loaded.as_ref().unwrap(), fetch);
+    }
+}
+
+/// Collects what reaches it and counts the flushes.
+#[derive(Clone, Default)]
+struct SharedWriter(Arc<Mutex<(Vec<u8>, usize)>>);
+
+impl SharedWriter {
+    fn lines(&self) -> usize {
+        self.0
+            .lock()
+            .unwrap()
+            .0
+            .iter()
+            .filter(|byte| **byte == b'\n')
+            .count()
+    }
+
+    fn flushes(&self) -> usize {
+        self.0.lock().unwrap().1
+    }
+}
+
+impl AsyncWrite for SharedWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.0.lock().unwrap().0.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.lock().unwrap().1 += 1;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn settle() {
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn lines_are_flushed_on_the_interval() {
+    let output = SharedWriter::default();
+    let (sender, receiver) = unbounded_channel();
+    let writer = spawn_ndjson_writer_with_interval(receiver, output.clone(), SECOND);
+    // Let the writer consume the interval's immediate first tick.
+    settle().await;
+
+    for fetch in fetches().into_iter().take(2) {
+        sender.send(fetch).unwrap();
+    }
+    settle().await;
+    assert_eq!((output.lines(), output.flushes()), (0, 0));
+
+    tokio::time::advance(SECOND).await;
+    settle().await;
+    assert_eq!((output.lines(), output.flushes()), (2, 1));
+
+    // An interval without new lines does not flush again.
+    tokio::time::advance(SECOND).await;
+    settle().await;
+    assert_eq!(output.flushes(), 1);
+
+    drop(sender);
+    writer.await.unwrap().unwrap();
+    assert_eq!((output.lines(), output.flushes()), (2, 2));
+}
@@ -0,0 +1,504 @@
+#![cfg(feature = "persistence")]
+
+use std::io::Cursor;
+
+use revm::{
+    db::{CacheDB, EmptyDB},
+    primitives::{AccountInfo, Address, Bytecode, Bytes, B256, U256},
+};
+use revm_proxy_db::{
+    append_fetches_to_file, apply_fetches_to_cache_db, cache_db_fingerprint, cache_db_from_bytes,
+    cache_db_to_bytes, load_bundle, load_cache_db_applying, load_cache_db_from_file,
+    load_cache_db_from_file_async, load_cache_db_from_file_bincode,
+    load_cache_db_from_file_streaming, load_cache_db_from_reader, load_cache_db_sharded,
+    load_cache_db_with_codec, load_cache_db_with_deltas, load_cache_db_with_fork, save_bundle,
+    save_cache_db_delta, save_cache_db_relative_to, save_cache_db_sharded, save_cache_db_to_file,
+    save_cache_db_to_file_async, save_cache_db_to_file_bincode,
+    save_cache_db_to_file_bincode_compressed, save_cache_db_to_file_compressed,
+    save_cache_db_to_file_with_format, save_cache_db_to_writer, save_cache_db_with_codec,
+    save_cache_db_with_fork, save_flattened_cache_db_to_file, BundleMetadata, CacheCodec,
+    ForkDescriptor, GzipCodec, JsonCodec, NewFetch, NumberFormat, ProxyDbError, RecordingDb,
+    CACHE_FORMAT_VERSION,
+};
+
+fn sample_cache() -> CacheDB<EmptyDB> {
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    for byte in 1..=8 {
+        let address = Address::repeat_byte(byte * 0x1f);
+        let info = AccountInfo {
+            balance: U256::from(byte) * U256::from(1_000),
+            nonce: byte.into(),
+            ..Default::default()
+        };
+        cache_db.insert_account_info(address, info);
+        cache_db
+            .insert_account_storage(address, U256::from(byte), U256::from(byte) + U256::from(1))
+            .unwrap();
+    }
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    cache_db.insert_account_info(
+        Address::repeat_byte(0xc0),
+        AccountInfo::from_bytecode(bytecode),
+    );
+    cache_db
+        .block_hashes
+        .insert(U256::from(100), B256::repeat_byte(0x64));
+    cache_db
+}
+
+/// `accounts` accounts holding two slots each, on top of [`sample_cache`].
+fn large_cache(accounts: u32) -> CacheDB<EmptyDB> {
+    let mut cache_db = sample_cache();
+    for i in 0..accounts {
+        let mut address = Address::ZERO;
+        address[16..].copy_from_slice(&i.to_be_bytes());
+        let info = AccountInfo {
+            balance: U256::from(i),
+            nonce: i.into(),
+            ..Default::default()
+        };
+        cache_db.insert_account_info(address, info);
+        for index in [0, u64::from(i)] {
+            cache_db
+                .insert_account_storage(address, U256::from(index), U256::from(i) + U256::from(1))
+                .unwrap();
+        }
+    }
+    cache_db
+}
+
+#[test]
+fn in_memory_round_trip() {
+    let cache_db = sample_cache();
+    let mut cursor = Cursor::new(Vec::new());
+    save_cache_db_to_writer(&mut cursor, &cache_db).unwrap();
+    cursor.set_position(0);
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_reader(cursor).unwrap();
+
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db)
+    );
+}
+
+#[test]
+fn compressed_and_legacy_files_load() {
+    let cache_db = sample_cache();
+    let dir = tempfile::tempdir().unwrap();
+
+    let compressed = dir.path().join("compressed.json.gz");
+    save_cache_db_to_file_compressed(&compressed, &cache_db).unwrap();
+    assert!(std::fs::read(&compressed)
+        .unwrap()
+        .starts_with(&[0x1f, 0x8b]));
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file(&compressed).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db)
+    );
+
+    // Caches saved before the version header are plain JSON starting with `{`.
+    let legacy = dir.path().join("legacy.json");
+    let bytes = cache_db_to_bytes(&cache_db).unwrap();
+    std::fs::write(&legacy, &bytes[6..]).unwrap();
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file(&legacy).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db)
+    );
+}
+
+#[tokio::test]
+async fn async_round_trip() {
+    let cache_db = sample_cache();
+    let fingerprint = cache_db_fingerprint(&cache_db);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cache.json");
+
+    save_cache_db_to_file_async(&path, cache_db).await.unwrap();
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file_async(&path).await.unwrap();
+    assert_eq!(cache_db_fingerprint(&loaded), fingerprint);
+}
+
+#[test]
+fn unknown_versions_are_rejected() {
+    let mut bytes = cache_db_to_bytes(&sample_cache()).unwrap();
+    assert_eq!(&bytes[..4], b"RPDB");
+    assert_eq!(bytes[4..6], CACHE_FORMAT_VERSION.to_le_bytes());
+
+    let newer = CACHE_FORMAT_VERSION + 1;
+    bytes[4..6].copy_from_slice(&newer.to_le_bytes());
+    let result = cache_db_from_bytes::<EmptyDB>(&bytes);
+    assert!(matches!(result, Err(ProxyDbError::UnsupportedVersion(version)) if version == newer));
+}
+
+#[test]
+fn sharded_round_trip() {
+    let cache_db = sample_cache();
+    let dir = tempfile::tempdir().unwrap();
+    let shards = |dir: &std::path::Path| {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("shard-"))
+            .collect();
+        names.sort();
+        names
+    };
+
+    save_cache_db_sharded(dir.path(), &cache_db, 8).unwrap();
+    assert_eq!(shards(dir.path()).len(), 8);
+    save_cache_db_sharded(dir.path(), &cache_db, 4).unwrap();
+    assert_eq!(
+        shards(dir.path()),
+        [
+            "shard-0.json",
+            "shard-1.json",
+            "shard-2.json",
+            "shard-3.json"
+        ]
+    );
+    let shard: CacheDB<EmptyDB> = load_cache_db_from_file(dir.path().join("shard-0.json")).unwrap();
+    assert!(!shard.accounts.is_empty() && shard.accounts.len() < cache_db.accounts.len());
+
+    let loaded: CacheDB<EmptyDB> = load_cache_db_sharded(dir.path()).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db)
+    );
+}
+
+#[test]
+fn delta_round_trip_with_removals() {
+    let base = sample_cache();
+    let mut current = base.clone();
+    let mut addresses: Vec<_> = current.accounts.keys().copied().collect();
+    addresses.sort();
+    current.accounts.remove(&addresses[0]);
+    let changed = current.accounts.get_mut(&addresses[1]).unwrap();
+    changed.info.balance = U256::from(1);
+    changed.storage.clear();
+    changed.storage.insert(U256::from(100), U256::from(100));
+    current.insert_account_info(Address::repeat_byte(0xee), AccountInfo::default());
+    current.block_hashes.clear();
+    assert_ne!(cache_db_fingerprint(&current), cache_db_fingerprint(&base));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("delta.json");
+    save_cache_db_relative_to(&path, &current, &base).unwrap();
+    let loaded = load_cache_db_applying(&path, &base).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&current)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn concurrent_saves_leave_one_complete_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cache.json");
+    let caches: Vec<_> = (0..4)
+        .map(|byte| {
+            let mut cache_db = sample_cache();
+            cache_db.insert_account_info(Address::with_last_byte(byte), AccountInfo::default());
+            cache_db
+        })
+        .collect();
+    let fingerprints: Vec<_> = caches.iter().map(cache_db_fingerprint).collect();
+
+    let mut caches = caches.into_iter();
+    let threads: Vec<_> = caches
+        .by_ref()
+        .take(2)
+        .map(|cache_db| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10 {
+                    save_cache_db_to_file(&path, &cache_db).unwrap();
+                }
+            })
+        })
+        .collect();
+    let tasks: Vec<_> = caches
+        .map(|cache_db| {
+            let path = path.clone();
+            let cache_db = std::sync::Arc::new(cache_db);
+            tokio::spawn(async move {
+                for _ in 0..10 {
+                    save_cache_db_to_file_async(&path, cache_db.clone())
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file(&path).unwrap();
+    assert!(fingerprints.contains(&cache_db_fingerprint(&loaded)));
+    let leftovers = std::fs::read_dir(dir.path()).unwrap().count();
+    assert_eq!(leftovers, 1);
+}
+
+#[test]
+fn bincode_round_trip_of_a_large_cache() {
+    let cache_db = large_cache(5_000);
+    let fingerprint = cache_db_fingerprint(&cache_db);
+    let dir = tempfile::tempdir().unwrap();
+
+    let plain = dir.path().join("cache.bin");
+    save_cache_db_to_file_bincode(&plain, &cache_db).unwrap();
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file_bincode(&plain).unwrap();
+    assert_eq!(loaded.accounts.len(), cache_db.accounts.len());
+    assert_eq!(cache_db_fingerprint(&loaded), fingerprint);
+
+    let compressed = dir.path().join("cache.bin.gz");
+    save_cache_db_to_file_bincode_compressed(&compressed, &cache_db).unwrap();
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file_bincode(&compressed).unwrap();
+    assert_eq!(cache_db_fingerprint(&loaded), fingerprint);
+}
+
+#[test]
+fn streaming_loads_a_large_cache_like_the_buffered_loader() {
+    let cache_db = large_cache(5_000);
+    let fingerprint = cache_db_fingerprint(&cache_db);
+    let dir = tempfile::tempdir().unwrap();
+    let plain = dir.path().join("cache.json");
+    save_cache_db_to_file(&plain, &cache_db).unwrap();
+    let compressed = dir.path().join("cache.json.gz");
+    save_cache_db_to_file_compressed(&compressed, &cache_db).unwrap();
+
+    for path in [plain, compressed] {
+        let buffered: CacheDB<EmptyDB> = load_cache_db_from_file(&path).unwrap();
+        let streamed: CacheDB<EmptyDB> = load_cache_db_from_file_streaming(&path).unwrap();
+        assert_eq!(streamed.accounts.len(), cache_db.accounts.len());
+        assert_eq!(cache_db_fingerprint(&buffered), fingerprint);
+        assert_eq!(cache_db_fingerprint(&streamed), fingerprint);
+    }
+}
+
+#[test]
+fn chained_deltas_rebuild_the_updated_cache() {
+    let base = sample_cache();
+    let address = Address::repeat_byte(0x1f);
+    let mut first = base.clone();
+    first.accounts.get_mut(&address).unwrap().info.balance = U256::from(1);
+    first
+        .insert_account_storage(address, U256::from(2), U256::from(2))
+        .unwrap();
+    let mut second = first.clone();
+    second.insert_account_info(Address::repeat_byte(0xee), AccountInfo::default());
+    second
+        .block_hashes
+        .insert(U256::from(101), B256::repeat_byte(0x65));
+
+    let dir = tempfile::tempdir().unwrap();
+    let paths = ["base.json", "first.json", "second.json"].map(|name| dir.path().join(name));
+    save_cache_db_to_file(&paths[0], &base).unwrap();
+    save_cache_db_delta(&paths[1], &base, &first).unwrap();
+    save_cache_db_delta(&paths[2], &first, &second).unwrap();
+
+    let delta: CacheDB<EmptyDB> = load_cache_db_from_file(&paths[1]).unwrap();
+    assert_eq!(delta.accounts.len(), 1);
+    assert_eq!(delta.accounts[&address].storage.len(), 1);
+    let loaded: CacheDB<EmptyDB> = load_cache_db_with_deltas(&paths[0], &paths[1..2]).unwrap();
+    assert_eq!(cache_db_fingerprint(&loaded), cache_db_fingerprint(&first));
+    let loaded: CacheDB<EmptyDB> = load_cache_db_with_deltas(&paths[0], &paths[1..]).unwrap();
+    assert_eq!(cache_db_fingerprint(&loaded), cache_db_fingerprint(&second));
+}
+
+#[test]
+fn decimal_numbers_load_like_hex_ones() {
+    // Balances of the sample accounts are multiples of 1000, or 0x3e8.
+    let cache_db = sample_cache();
+    let dir = tempfile::tempdir().unwrap();
+    let [hex, decimal] = ["hex.json", "decimal.json"].map(|name| dir.path().join(name));
+    save_cache_db_to_file_with_format(&hex, &cache_db, NumberFormat::Hex).unwrap();
+    save_cache_db_to_file_with_format(&decimal, &cache_db, NumberFormat::Decimal).unwrap();
+
+    let text = |path| String::from_utf8_lossy(&std::fs::read(path).unwrap()).into_owned();
+    assert!(text(&hex).contains(r#""balance":"0x3e8""#));
+    let decimal_text = text(&decimal);
+    assert!(decimal_text.contains(r#""balance":"1000""#));
+    assert!(decimal_text.contains(r#""100":"#));
+    assert!(!decimal_text.contains(r#""0x3e8""#));
+
+    let from_hex: CacheDB<EmptyDB> = load_cache_db_from_file(&hex).unwrap();
+    let from_decimal: CacheDB<EmptyDB> = load_cache_db_from_file(&decimal).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&from_decimal),
+        cache_db_fingerprint(&from_hex)
+    );
+    assert_eq!(
+        cache_db_fingerprint(&from_hex),
+        cache_db_fingerprint(&cache_db)
+    );
+}
+
+/// JSON behind a magic prefix, standing in for a format the crate does not ship.
+struct TaggedCodec;
+
+impl TaggedCodec {
+    const TAG: &'static [u8] = b"TAGGED";
+}
+
+impl CacheCodec for TaggedCodec {
+    fn encode(&self, cache_db: &CacheDB<EmptyDB>) -> Result<Vec<u8>, ProxyDbError> {
+        Ok([Self::TAG, &JsonCodec.encode(cache_db)?].concat())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<CacheDB<EmptyDB>, ProxyDbError> {
+        let json = bytes
+            .strip_prefix(Self::TAG)
+            .ok_or_else(|| ProxyDbError::Codec("missing tag".into()))?;
+        JsonCodec.decode(json)
+    }
+}
+
+#[test]
+fn custom_codec_round_trip() {
+    let cache_db = sample_cache();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cache.tagged");
+    save_cache_db_with_codec(&path, &cache_db, &TaggedCodec).unwrap();
+    assert!(std::fs::read(&path).unwrap().starts_with(TaggedCodec::TAG));
+
+    let loaded = load_cache_db_with_codec(&path, &TaggedCodec).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db)
+    );
+    let compressed = dir.path().join("cache.tagged.gz");
+    save_cache_db_with_codec(&compressed, &cache_db, &GzipCodec(TaggedCodec)).unwrap();
+    let loaded = load_cache_db_with_codec(&compressed, &GzipCodec(TaggedCodec)).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db)
+    );
+
+    let result = load_cache_db_with_codec(&compressed, &TaggedCodec);
+    assert!(matches!(result, Err(ProxyDbError::Codec(_))));
+}
+
+#[test]
+fn flattened_save_keeps_every_layer_with_the_top_one_winning() {
+    let (shared, bottom_only) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let mut bottom = CacheDB::new(EmptyDB::new());
+    bottom.insert_account_info(shared, AccountInfo::default());
+    bottom.insert_account_info(bottom_only, AccountInfo::default());
+    for (index, value) in [(1, 1), (2, 2)] {
+        bottom
+            .insert_account_storage(shared, U256::from(index), U256::from(value))
+            .unwrap();
+    }
+    let mut top = CacheDB::new(RecordingDb::new(bottom));
+    top.insert_account_info(
+        shared,
+        AccountInfo {
+            nonce: 7,
+            ..Default::default()
+        },
+    );
+    top.insert_account_storage(shared, U256::from(2), U256::from(20))
+        .unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cache.json");
+    save_flattened_cache_db_to_file(&path, &top).unwrap();
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file(&path).unwrap();
+    assert_eq!(loaded.accounts.len(), 2);
+    let account = &loaded.accounts[&shared];
+    assert_eq!(account.info.nonce, 7);
+    assert_eq!(account.storage[&U256::from(1)], U256::from(1));
+    assert_eq!(account.storage[&U256::from(2)], U256::from(20));
+    assert!(loaded.accounts.contains_key(&bottom_only));
+}
+
+#[test]
+fn appending_twice_accumulates_fetches() {
+    let storage = |byte: u8| NewFetch::Storage {
+        address: Address::with_last_byte(byte),
+        index: U256::from(byte),
+        value: U256::from(byte),
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let fresh = dir.path().join("fresh.json");
+    let gzipped = dir.path().join("cache.json.gz");
+    save_cache_db_to_file_compressed(&gzipped, &sample_cache()).unwrap();
+
+    for path in [&fresh, &gzipped] {
+        append_fetches_to_file(path, [storage(1), storage(2)]).unwrap();
+        append_fetches_to_file(path, [storage(3)]).unwrap();
+    }
+
+    let mut expected = CacheDB::new(EmptyDB::new());
+    apply_fetches_to_cache_db(&mut expected, (1..=3).map(storage)).unwrap();
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file(&fresh).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&expected)
+    );
+
+    let mut expected = sample_cache();
+    apply_fetches_to_cache_db(&mut expected, (1..=3).map(storage)).unwrap();
+    assert!(!std::fs::read(&gzipped).unwrap().starts_with(&[0x1f, 0x8b]));
+    let loaded: CacheDB<EmptyDB> = load_cache_db_from_file(&gzipped).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&expected)
+    );
+}
+
+#[test]
+fn bundle_round_trip() {
+    let cache_db = sample_cache();
+    let fetches = vec![
+        NewFetch::BasicMissing {
+            address: Address::with_last_byte(1),
+        },
+        NewFetch::BlockHash {
+            number: 100,
+            hash: B256::repeat_byte(0x64),
+        },
+    ];
+    let metadata = BundleMetadata {
+        chain_id: 1,
+        block_number: 100,
+        timestamp: 1_700_000_000,
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.json");
+    save_bundle(&path, &cache_db, &fetches, &metadata).unwrap();
+
+    let bundle = load_bundle(&path).unwrap();
+    assert_eq!(bundle.metadata, metadata);
+    assert_eq!(bundle.fetches, fetches);
+    assert_eq!(
+        cache_db_fingerprint(&bundle.cache_db),
+        cache_db_fingerprint(&cache_db)
+    );
+}
+
+#[test]
+fn fork_descriptor_round_trips_with_its_cache() {
+    let cache_db = sample_cache();
+    let fork = ForkDescriptor {
+        chain_id: 10,
+        block_number: 120_000_000,
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("fork.json");
+    save_cache_db_with_fork(&path, &cache_db, &fork).unwrap();
+
+    let (loaded, loaded_fork) = load_cache_db_with_fork(&path).unwrap();
+    assert_eq!(loaded_fork, fork);
+    assert_eq!(
+        cache_db_fingerprint(&loaded),
+        cache_db_fingerprint(&cache_db)
+    );
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use revm::{
+    db::{AccountState, CacheDB},
+    primitives::{AccountInfo, Address, Bytecode, Bytes, U256},
+};
+use revm_proxy_db::{
+    prefetch_into_cache_db, prefetch_into_cache_db_async, MockDb, PrefetchErrorPolicy,
+};
+use tokio::time::Instant;
+
+#[test]
+fn prefetch_loads_accounts_code_and_missing_slots() {
+    let [contract, missing] = [1, 2].map(Address::with_last_byte);
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let mock = MockDb::new()
+        .with_account(
+            contract,
+            AccountInfo {
+                code: None,
+                ..AccountInfo::from_bytecode(bytecode.clone())
+            },
+        )
+        .with_storage(contract, U256::from(1), U256::from(10))
+        .with_storage(contract, U256::from(2), U256::from(20))
+        .with_code(bytecode.clone());
+    let mut cache_db = CacheDB::new(mock);
+    cache_db
+        .insert_account_storage(contract, U256::from(2), U256::from(99))
+        .unwrap();
+    let access_list = [
+        (contract, vec![U256::from(1), U256::from(2)]),
+        (missing, vec![U256::from(1)]),
+    ];
+
+    prefetch_into_cache_db(&mut cache_db, &access_list).unwrap();
+    let account = &cache_db.accounts[&contract];
+    assert_eq!(account.storage[&U256::from(1)], U256::from(10));
+    // Cached keys are kept and not fetched again.
+    assert_eq!(account.storage[&U256::from(2)], U256::from(99));
+    assert_eq!(cache_db.contracts[&bytecode.hash_slow()], bytecode);
+    assert_eq!(
+        cache_db.accounts[&missing].account_state,
+        AccountState::NotExisting
+    );
+    // The contract itself was loaded when its slot was cached.
+    let calls = cache_db.db.calls();
+    assert_eq!(
+        (calls.basic_reads, calls.storage_reads, calls.code_reads),
+        (2, 1, 1)
+    );
+
+    prefetch_into_cache_db(&mut cache_db, &access_list).unwrap();
+    assert_eq!(cache_db.db.calls().storage_reads, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn async_prefetch_runs_lookups_concurrently() {
+    let delay = Duration::from_secs(1);
+    let addresses: Vec<_> = (1..=8).map(Address::with_last_byte).collect();
+    let mock = addresses.iter().fold(MockDb::new(), |mock, address| {
+        mock.with_account(*address, AccountInfo::default())
+            .with_storage(*address, U256::from(1), U256::from(1))
+    });
+    let access_list: Vec<_> = addresses
+        .iter()
+        .map(|address| (*address, vec![U256::from(1), U256::from(2)]))
+        .collect();
+
+    let mut cache_db = CacheDB::new(mock.with_delay(delay));
+    let start = Instant::now();
+    let failures =
+        prefetch_into_cache_db_async(&mut cache_db, &access_list, 32, PrefetchErrorPolicy::Abort)
+            .await
+            .unwrap();
+    assert!(failures.is_empty());
+    // One delay for the accounts, one for their slots.
+    assert_eq!(start.elapsed(), 2 * delay);
+    let calls = cache_db.db.calls();
+    assert_eq!((calls.basic_reads, calls.storage_reads), (8, 16));
+    for address in &addresses {
+        assert_eq!(
+            cache_db.accounts[address].storage[&U256::from(1)],
+            U256::from(1)
+        );
+    }
+
+    cache_db.accounts.clear();
+    let start = Instant::now();
+    prefetch_into_cache_db_async(&mut cache_db, &access_list, 1, PrefetchErrorPolicy::Abort)
+        .await
+        .unwrap();
+    assert_eq!(start.elapsed(), 24 * delay);
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "persistence")]
+
+use revm::{
+    primitives::{AccountInfo, Address, Bytecode, Bytes, B256, U256},
+    DatabaseRef,
+};
+use revm_proxy_db::{record_and_replay, MockDb};
+
+#[test]
+fn recorded_reads_replay_losslessly() {
+    let (holder, contract, missing) = (
+        Address::with_last_byte(1),
+        Address::with_last_byte(2),
+        Address::with_last_byte(3),
+    );
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let code_hash = bytecode.hash_slow();
+    let live = MockDb::new()
+        .with_account(
+            holder,
+            AccountInfo {
+                balance: U256::from(1_000),
+                nonce: 3,
+                ..Default::default()
+            },
+        )
+        .with_account(
+            contract,
+            AccountInfo {
+                code: None,
+                ..AccountInfo::from_bytecode(bytecode.clone())
+            },
+        )
+        .with_storage(holder, U256::from(1), U256::from(11))
+        .with_storage(contract, U256::ZERO, U256::from(20))
+        .with_storage(contract, U256::MAX, U256::from(21))
+        .with_code(bytecode)
+        .with_block_hash(99, B256::repeat_byte(0x63))
+        .with_block_hash(100, B256::repeat_byte(0x64));
+    let dir = tempfile::tempdir().unwrap();
+
+    let mismatches = record_and_replay(live, dir.path().join("cache.json"), |db| {
+        // Storage first, before its account is fetched.
+        db.storage_ref(holder, U256::from(1)).unwrap();
+        db.storage_ref(contract, U256::MAX).unwrap();
+        db.basic_ref(holder).unwrap();
+        db.basic_ref(contract).unwrap();
+        db.storage_ref(contract, U256::ZERO).unwrap();
+        db.storage_ref(holder, U256::from(2)).unwrap();
+        db.basic_ref(missing).unwrap();
+        db.code_by_hash_ref(code_hash).unwrap();
+        db.block_hash_ref(99).unwrap();
+        db.block_hash_ref(100).unwrap();
+    })
+    .unwrap();
+    assert_eq!(mismatches, []);
+}
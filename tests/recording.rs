@@ -0,0 +1,933 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use revm::{
+    db::{AccountState, CacheDB, EmptyDB},
+    primitives::{
+        Account, AccountInfo, AccountStatus, Address, Bytecode, Bytes, EvmStorageSlot, B256, U256,
+    },
+    DatabaseCommit, DatabaseRef,
+};
+use revm_proxy_db::{
+    apply_fetches_to_cache_db, drain_receiver, AsyncDatabaseRef, BlockHashPolicy, DbStats,
+    FetchKey, FetchKind, FlakyDb, MockDb, NewFetch, NoopSink, RecordingDb, Unavailable,
+    UniqueStats,
+};
+
+#[tokio::test]
+async fn code_by_hash_is_replayed_into_contracts() {
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let code_hash = bytecode.hash_slow();
+    let (db, receiver) = RecordingDb::new_with_channel(MockDb::new().with_code(bytecode.clone()));
+
+    assert_eq!(db.code_by_hash_ref(code_hash).unwrap(), bytecode);
+    drop(db);
+    let fetches = drain_receiver(receiver).await;
+    assert_eq!(
+        fetches,
+        [NewFetch::CodeByHash {
+            code_hash,
+            bytecode: bytecode.clone()
+        }]
+    );
+
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    apply_fetches_to_cache_db(&mut cache_db, fetches).unwrap();
+    assert_eq!(cache_db.contracts.get(&code_hash), Some(&bytecode));
+}
+
+#[tokio::test]
+async fn dedup_records_a_slot_once() {
+    let address = Address::with_last_byte(1);
+    let mock = MockDb::new().with_storage(address, U256::from(1), U256::from(2));
+    let (db, receiver) = RecordingDb::new_with_channel(mock);
+    let db = db.with_dedup();
+    for _ in 0..3 {
+        assert_eq!(
+            db.storage_ref(address, U256::from(1)).unwrap(),
+            U256::from(2)
+        );
+    }
+
+    drop(db);
+    assert_eq!(
+        drain_receiver(receiver).await,
+        [NewFetch::Storage {
+            address,
+            index: U256::from(1),
+            value: U256::from(2)
+        }]
+    );
+}
+
+#[tokio::test]
+async fn read_cache_hits_the_inner_db_once_per_key() {
+    let address = Address::with_last_byte(1);
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let code_hash = bytecode.hash_slow();
+    let mock = MockDb::new()
+        .with_account(address, AccountInfo::default())
+        .with_storage(address, U256::from(1), U256::from(2))
+        .with_code(bytecode)
+        .with_block_hash(1, B256::repeat_byte(1));
+    let (db, receiver) = RecordingDb::new_with_channel(mock);
+    let db = db.with_read_cache();
+    for _ in 0..3 {
+        db.basic_ref(address).unwrap();
+        db.storage_ref(address, U256::from(1)).unwrap();
+        db.code_by_hash_ref(code_hash).unwrap();
+        db.block_hash_ref(1).unwrap();
+    }
+
+    let once = DbStats {
+        basic_reads: 1,
+        storage_reads: 1,
+        code_reads: 1,
+        block_hash_reads: 1,
+        unique: None,
+    };
+    assert_eq!(db.inner().calls(), once);
+    assert_eq!(db.stats(), once);
+    drop(db);
+    assert_eq!(drain_receiver(receiver).await.len(), 4);
+}
+
+#[test]
+fn read_cache_does_not_mask_errors() {
+    let db = RecordingDb::new(FlakyDb::failing(1)).with_read_cache();
+    let address = Address::with_last_byte(1);
+
+    assert!(db.basic_ref(address).is_err());
+    assert_eq!(db.basic_ref(address).unwrap(), None);
+    assert_eq!(db.basic_ref(address).unwrap(), None);
+    assert_eq!(db.inner().attempts(), 2);
+}
+
+#[tokio::test]
+async fn dedup_records_a_block_hash_once() {
+    let hash = B256::repeat_byte(1);
+    let (db, receiver) = RecordingDb::new_with_channel(MockDb::new().with_block_hash(1, hash));
+    let db = db.with_dedup();
+    for _ in 0..2 {
+        assert_eq!(db.block_hash_ref(1).unwrap(), hash);
+        // Out of the window, the zero hash is recorded once too.
+        assert_eq!(db.block_hash_ref(1_000).unwrap(), B256::ZERO);
+    }
+
+    drop(db);
+    assert_eq!(
+        drain_receiver(receiver).await,
+        [
+            NewFetch::BlockHash { number: 1, hash },
+            NewFetch::BlockHash {
+                number: 1_000,
+                hash: B256::ZERO
+            }
+        ]
+    );
+}
+
+#[tokio::test]
+async fn inner_errors_are_recorded_and_returned() {
+    let address = Address::with_last_byte(1);
+    let (db, receiver) = RecordingDb::new_with_channel(FlakyDb::failing(2));
+
+    assert!(matches!(db.basic_ref(address), Err(Unavailable { .. })));
+    assert!(matches!(
+        db.storage_ref(address, U256::from(1)),
+        Err(Unavailable { .. })
+    ));
+    assert_eq!(db.basic_ref(address).unwrap(), None);
+    drop(db);
+    assert_eq!(
+        drain_receiver(receiver).await,
+        [
+            NewFetch::Error {
+                kind: FetchKind::Basic,
+                address: Some(address),
+                index: None,
+                message: "backend unavailable".into(),
+            },
+            NewFetch::Error {
+                kind: FetchKind::Storage,
+                address: Some(address),
+                index: Some(U256::from(1)),
+                message: "backend unavailable".into(),
+            },
+            NewFetch::BasicMissing { address },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn dedup_capacity_evicts_the_least_recently_seen_key() {
+    let address = Address::with_last_byte(1);
+    let (db, receiver) = RecordingDb::new_with_channel(MockDb::new());
+    let db = db.with_dedup_capacity(2);
+    for index in [1, 2, 1, 3, 2, 1] {
+        db.storage_ref(address, U256::from(index)).unwrap();
+    }
+
+    drop(db);
+    let indexes: Vec<_> = drain_receiver(receiver)
+        .await
+        .into_iter()
+        .map(|fetch| match fetch {
+            NewFetch::Storage { index, .. } => index.to::<u64>(),
+            fetch => panic!("unexpected {fetch:?}"),
+        })
+        .collect();
+    // Seeing 3 evicts 2, as 1 was seen again since, and seeing 2 again then evicts 1.
+    assert_eq!(indexes, [1, 2, 3, 2, 1]);
+}
+
+#[test]
+fn read_cache_capacity_refetches_evicted_keys() {
+    let address = Address::with_last_byte(1);
+    let db = RecordingDb::new(MockDb::new()).with_read_cache_capacity(1);
+    for index in [1, 1, 2, 1] {
+        db.storage_ref(address, U256::from(index)).unwrap();
+    }
+    assert_eq!(db.inner().calls().storage_reads, 3);
+}
+
+#[test]
+fn latency_histogram_buckets_slow_calls() {
+    let inner = MockDb::new().with_delay(Duration::from_millis(20));
+    let (db, _receiver) = RecordingDb::new_with_channel(inner);
+    let db = db.with_latency_histogram();
+    for byte in 0..3 {
+        db.basic_ref(Address::with_last_byte(byte)).unwrap();
+    }
+
+    let histogram = db.latency_histogram().unwrap();
+    assert_eq!(histogram.count(), 3);
+    let slow = histogram
+        .buckets
+        .iter()
+        .find(|bucket| bucket.upper_bound == Some(Duration::from_millis(100)))
+        .unwrap();
+    assert_eq!(slow.count, 3);
+    assert!(histogram.mean() >= Duration::from_millis(20));
+}
+
+#[tokio::test]
+async fn record_limit_caps_the_emitted_events() {
+    let (db, receiver) = RecordingDb::new_with_channel(MockDb::new());
+    let db = db.with_record_limit(5);
+    for byte in 0..10 {
+        db.basic_ref(Address::with_last_byte(byte)).unwrap();
+        assert_eq!(db.record_complete(), byte >= 4);
+    }
+    assert_eq!(db.inner().calls().basic_reads, 10);
+
+    drop(db);
+    let fetches = drain_receiver(receiver).await;
+    assert_eq!(fetches.len(), 5);
+    assert_eq!(
+        fetches.last(),
+        Some(&NewFetch::BasicMissing {
+            address: Address::with_last_byte(4)
+        })
+    );
+}
+
+#[tokio::test]
+async fn zero_block_hashes_follow_the_policy() {
+    let known = B256::repeat_byte(0x64);
+    let recorded = NewFetch::BlockHash {
+        number: 100,
+        hash: known,
+    };
+    let cases = [
+        (
+            BlockHashPolicy::Record,
+            Some(NewFetch::BlockHash {
+                number: 5,
+                hash: B256::ZERO,
+            }),
+        ),
+        (BlockHashPolicy::Skip, None),
+        (
+            BlockHashPolicy::Error,
+            Some(NewFetch::Error {
+                kind: FetchKind::BlockHash,
+                address: None,
+                index: None,
+                message: "no hash for block 5".to_owned(),
+            }),
+        ),
+    ];
+    for (policy, expected) in cases {
+        let inner = MockDb::new().with_block_hash(100, known);
+        let (db, receiver) = RecordingDb::new_with_channel(inner);
+        let db = db.with_block_hash_policy(policy);
+        assert_eq!(db.block_hash_ref(5).unwrap(), B256::ZERO);
+        assert_eq!(db.block_hash_ref(100).unwrap(), known);
+        drop(db);
+
+        let expected: Vec<_> = expected.into_iter().chain([recorded.clone()]).collect();
+        assert_eq!(drain_receiver(receiver).await, expected, "{policy:?}");
+    }
+}
+
+#[test]
+fn unique_stats_count_distinct_keys() {
+    let (first, second) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let db = RecordingDb::new(MockDb::new()).with_unique_stats();
+    for _ in 0..3 {
+        db.basic_ref(first).unwrap();
+        db.storage_ref(first, U256::from(1)).unwrap();
+        db.storage_ref(second, U256::from(1)).unwrap();
+        db.block_hash_ref(7).unwrap();
+    }
+    db.basic_ref(second).unwrap();
+
+    assert_eq!(
+        db.stats(),
+        DbStats {
+            basic_reads: 4,
+            storage_reads: 6,
+            code_reads: 0,
+            block_hash_reads: 3,
+            unique: Some(UniqueStats {
+                accounts: 2,
+                storage_slots: 2,
+                code_hashes: 0,
+                block_numbers: 1,
+            }),
+        }
+    );
+    assert_eq!(RecordingDb::new(MockDb::new()).stats().unique, None);
+}
+
+#[test]
+fn address_filter_only_records_accepted_accounts() {
+    let (accepted, rejected) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let code = |byte| Bytecode::new_raw(Bytes::from(vec![0x60, byte, 0x00]));
+    let (accepted_code, rejected_code) = (code(1), code(2));
+    let account = |bytecode: &Bytecode| AccountInfo {
+        code: None,
+        ..AccountInfo::from_bytecode(bytecode.clone())
+    };
+    let mock = MockDb::new()
+        .with_account(accepted, account(&accepted_code))
+        .with_account(rejected, account(&rejected_code))
+        .with_storage(rejected, U256::from(1), U256::from(2))
+        .with_code(accepted_code.clone())
+        .with_code(rejected_code.clone());
+    let db =
+        RecordingDb::new_recording(mock).with_address_filter(move |address| *address == accepted);
+    for address in [accepted, rejected] {
+        db.basic_ref(address).unwrap();
+        db.storage_ref(address, U256::from(1)).unwrap();
+    }
+    db.code_by_hash_ref(accepted_code.hash_slow()).unwrap();
+    assert_eq!(
+        db.code_by_hash_ref(rejected_code.hash_slow()).unwrap(),
+        rejected_code
+    );
+    db.block_hash_ref(1).unwrap();
+
+    assert_eq!(
+        db.take_fetches(),
+        [
+            NewFetch::Basic {
+                address: accepted,
+                account_info: account(&accepted_code)
+            },
+            NewFetch::Storage {
+                address: accepted,
+                index: U256::from(1),
+                value: U256::ZERO
+            },
+            NewFetch::CodeByHash {
+                code_hash: accepted_code.hash_slow(),
+                bytecode: accepted_code
+            },
+            NewFetch::BlockHash {
+                number: 1,
+                hash: B256::ZERO
+            },
+        ]
+    );
+    assert_eq!(db.stats().storage_reads, 2);
+}
+
+#[test]
+fn dropped_receiver_leaves_the_sink_unhealthy() {
+    let address = Address::with_last_byte(1);
+    let mock = MockDb::new().with_storage(address, U256::from(1), U256::from(2));
+    let (db, receiver) = RecordingDb::new_with_channel(mock);
+    assert!(db.sink_healthy());
+
+    drop(receiver);
+    assert!(!db.sink_healthy());
+    assert_eq!(
+        db.storage_ref(address, U256::from(1)).unwrap(),
+        U256::from(2)
+    );
+    assert_eq!(db.basic_ref(address).unwrap(), None);
+    assert_eq!(db.stats().storage_reads, 1);
+}
+
+#[tokio::test]
+async fn clones_on_several_threads_share_dedup_and_stats() {
+    let shared = Address::with_last_byte(0xff);
+    let (db, receiver) = RecordingDb::new_with_channel(Arc::new(MockDb::new()));
+    let db = db.with_dedup();
+    std::thread::scope(|scope| {
+        for thread in 0..4 {
+            let db = db.clone();
+            scope.spawn(move || {
+                db.storage_ref(shared, U256::from(1)).unwrap();
+                db.basic_ref(Address::with_last_byte(thread)).unwrap();
+            });
+        }
+    });
+
+    let stats = db.stats();
+    assert_eq!((stats.basic_reads, stats.storage_reads), (4, 4));
+    drop(db);
+    let fetches = drain_receiver(receiver).await;
+    assert_eq!(fetches.len(), 5);
+    let storage = fetches
+        .iter()
+        .filter(|fetch| matches!(fetch, NewFetch::Storage { .. }))
+        .count();
+    assert_eq!(storage, 1);
+    for thread in 0..4 {
+        let address = Address::with_last_byte(thread);
+        assert!(fetches.contains(&NewFetch::BasicMissing { address }));
+    }
+}
+
+#[tokio::test]
+async fn timestamps_follow_the_inner_db_latency() {
+    let delay = Duration::from_millis(20);
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let db = RecordingDb::new(MockDb::new().with_delay(delay))
+        .with_timestamps(std::time::Instant::now(), sender);
+    db.basic_ref(Address::ZERO).unwrap();
+    db.block_hash_ref(1).unwrap();
+    drop(db);
+
+    let mut timed = Vec::new();
+    while let Some(fetch) = receiver.recv().await {
+        timed.push(fetch);
+    }
+    assert_eq!(timed.len(), 2);
+    assert_eq!(
+        timed[0].fetch,
+        NewFetch::BasicMissing {
+            address: Address::ZERO
+        }
+    );
+    assert!(matches!(
+        timed[1].fetch,
+        NewFetch::BlockHash { number: 1, .. }
+    ));
+    assert!(timed[0].at >= delay);
+    assert!(timed[1].at >= timed[0].at + delay);
+}
+
+#[test]
+fn take_fetches_drains_the_buffer_in_call_order() {
+    let address = Address::with_last_byte(1);
+    let mock = MockDb::new().with_storage(address, U256::from(1), U256::from(2));
+    let db = RecordingDb::new_recording(mock);
+    assert_eq!(db.take_fetches(), []);
+
+    db.storage_ref(address, U256::from(1)).unwrap();
+    db.basic_ref(address).unwrap();
+    assert_eq!(
+        db.take_fetches(),
+        [
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(2)
+            },
+            NewFetch::BasicMissing { address },
+        ]
+    );
+    assert_eq!(db.take_fetches(), []);
+
+    db.block_hash_ref(1).unwrap();
+    assert_eq!(
+        db.take_fetches(),
+        [NewFetch::BlockHash {
+            number: 1,
+            hash: B256::ZERO
+        }]
+    );
+}
+
+#[tokio::test]
+async fn async_lookups_are_recorded_in_order() {
+    let address = Address::with_last_byte(1);
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let mock = MockDb::new()
+        .with_storage(address, U256::from(1), U256::from(2))
+        .with_code(bytecode.clone())
+        .with_block_hash(7, B256::repeat_byte(7));
+    let (db, receiver) = RecordingDb::new_with_channel(mock);
+    db.storage_async_ref(address, U256::from(1)).await.unwrap();
+    db.block_hash_async_ref(7).await.unwrap();
+    db.basic_async_ref(address).await.unwrap();
+    db.code_by_hash_async_ref(bytecode.hash_slow())
+        .await
+        .unwrap();
+
+    let stats = db.stats();
+    assert_eq!(
+        (
+            stats.basic_reads,
+            stats.storage_reads,
+            stats.code_reads,
+            stats.block_hash_reads
+        ),
+        (1, 1, 1, 1)
+    );
+    drop(db);
+    assert_eq!(
+        drain_receiver(receiver).await,
+        [
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(2)
+            },
+            NewFetch::BlockHash {
+                number: 7,
+                hash: B256::repeat_byte(7)
+            },
+            NewFetch::BasicMissing { address },
+            NewFetch::CodeByHash {
+                code_hash: bytecode.hash_slow(),
+                bytecode
+            },
+        ]
+    );
+}
+
+#[test]
+fn commits_reach_the_inner_db_and_invalidate_the_read_cache() {
+    let address = Address::with_last_byte(1);
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    cache_db.insert_account_info(address, AccountInfo::default());
+    cache_db
+        .insert_account_storage(address, U256::from(1), U256::from(1))
+        .unwrap();
+    let mut db = RecordingDb::new(cache_db).with_read_cache();
+    assert_eq!(db.basic_ref(address).unwrap().unwrap().nonce, 0);
+    assert_eq!(
+        db.storage_ref(address, U256::from(1)).unwrap(),
+        U256::from(1)
+    );
+
+    let account = Account {
+        info: AccountInfo {
+            nonce: 1,
+            ..Default::default()
+        },
+        storage: [(
+            U256::from(1),
+            EvmStorageSlot::new_changed(U256::from(1), U256::from(5)),
+        )]
+        .into_iter()
+        .collect(),
+        status: AccountStatus::Touched,
+    };
+    db.commit([(address, account)].into_iter().collect());
+
+    let committed = &db.inner().accounts[&address];
+    assert_eq!(committed.info.nonce, 1);
+    assert_eq!(committed.storage[&U256::from(1)], U256::from(5));
+    assert_eq!(db.basic_ref(address).unwrap().unwrap().nonce, 1);
+    assert_eq!(
+        db.storage_ref(address, U256::from(1)).unwrap(),
+        U256::from(5)
+    );
+    let stats = db.stats();
+    assert_eq!((stats.basic_reads, stats.storage_reads), (2, 2));
+}
+
+#[test]
+fn record_transform_redacts_without_changing_reads() {
+    let (secret, public) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let mock = MockDb::new()
+        .with_storage(secret, U256::from(1), U256::from(42))
+        .with_storage(public, U256::from(1), U256::from(7));
+    let db = RecordingDb::new_recording(mock).with_record_transform(move |fetch| match fetch {
+        NewFetch::Storage { address, index, .. } if *address == secret => Some(NewFetch::Storage {
+            address: *address,
+            index: *index,
+            value: U256::ZERO,
+        }),
+        NewFetch::BlockHash { .. } => None,
+        fetch => Some(fetch.clone()),
+    });
+
+    assert_eq!(
+        db.storage_ref(secret, U256::from(1)).unwrap(),
+        U256::from(42)
+    );
+    assert_eq!(
+        db.storage_ref(public, U256::from(1)).unwrap(),
+        U256::from(7)
+    );
+    db.block_hash_ref(1).unwrap();
+    assert_eq!(
+        db.take_fetches(),
+        [
+            NewFetch::Storage {
+                address: secret,
+                index: U256::from(1),
+                value: U256::ZERO
+            },
+            NewFetch::Storage {
+                address: public,
+                index: U256::from(1),
+                value: U256::from(7)
+            },
+        ]
+    );
+}
+
+#[test]
+fn cache_hits_are_recorded_once_per_repeated_read() {
+    let address = Address::with_last_byte(1);
+    let mock = MockDb::new().with_storage(address, U256::from(1), U256::from(2));
+    let db = RecordingDb::new_recording(mock)
+        .with_read_cache()
+        .with_dedup()
+        .record_cache_hits();
+    for _ in 0..3 {
+        assert_eq!(
+            db.storage_ref(address, U256::from(1)).unwrap(),
+            U256::from(2)
+        );
+    }
+    db.block_hash_ref(1).unwrap();
+    db.block_hash_ref(1).unwrap();
+
+    let key = FetchKey::Storage(address, U256::from(1));
+    assert_eq!(
+        db.take_fetches(),
+        [
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(2)
+            },
+            NewFetch::CacheHit { key },
+            NewFetch::CacheHit { key },
+            NewFetch::BlockHash {
+                number: 1,
+                hash: B256::ZERO
+            },
+            NewFetch::CacheHit {
+                key: FetchKey::BlockHash(1)
+            },
+        ]
+    );
+    assert_eq!(db.inner().calls().storage_reads, 1);
+}
+
+#[test]
+fn reset_clears_dedup_stats_and_the_read_cache() {
+    let address = Address::with_last_byte(1);
+    let mock = MockDb::new().with_storage(address, U256::from(1), U256::from(2));
+    let db = RecordingDb::new_recording(mock)
+        .with_dedup()
+        .with_read_cache()
+        .with_unique_stats();
+    let read = || db.storage_ref(address, U256::from(1)).unwrap();
+    read();
+    read();
+    assert_eq!(db.take_fetches().len(), 1);
+    assert_eq!(db.inner().calls().storage_reads, 1);
+
+    db.reset();
+    assert_eq!(
+        db.stats(),
+        DbStats {
+            unique: Some(UniqueStats::default()),
+            ..Default::default()
+        }
+    );
+    read();
+    // The read cache was emptied, so the slot is fetched and recorded again.
+    assert_eq!(db.inner().calls().storage_reads, 2);
+    assert_eq!(
+        db.take_fetches(),
+        [NewFetch::Storage {
+            address,
+            index: U256::from(1),
+            value: U256::from(2)
+        }]
+    );
+    assert_eq!(db.stats().storage_reads, 1);
+}
+
+#[test]
+fn primed_keys_are_not_recorded_again() {
+    let (saved, new) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let mut saved_cache = CacheDB::new(EmptyDB::new());
+    saved_cache.insert_account_info(saved, AccountInfo::default());
+    saved_cache
+        .insert_account_storage(saved, U256::from(1), U256::from(1))
+        .unwrap();
+    saved_cache
+        .block_hashes
+        .insert(U256::from(1), B256::repeat_byte(1));
+    let db = RecordingDb::new_recording(MockDb::new()).prime_dedup_from(&saved_cache);
+
+    db.basic_ref(saved).unwrap();
+    db.storage_ref(saved, U256::from(1)).unwrap();
+    db.block_hash_ref(1).unwrap();
+    db.storage_ref(saved, U256::from(2)).unwrap();
+    db.basic_ref(new).unwrap();
+    db.basic_ref(new).unwrap();
+    assert_eq!(
+        db.take_fetches(),
+        [
+            NewFetch::Storage {
+                address: saved,
+                index: U256::from(2),
+                value: U256::ZERO
+            },
+            NewFetch::BasicMissing { address: new },
+        ]
+    );
+}
+
+#[test]
+fn noop_sink_records_nothing_and_serves_reads() {
+    let address = Address::with_last_byte(1);
+    let hash = B256::repeat_byte(1);
+    let mock = MockDb::new()
+        .with_account(address, AccountInfo::default())
+        .with_storage(address, U256::from(1), U256::from(2))
+        .with_block_hash(1, hash);
+    let emitted = Arc::new(AtomicUsize::new(0));
+    let counter = emitted.clone();
+    let db = RecordingDb::new(mock)
+        .with_sink(NoopSink)
+        .with_record_transform(move |fetch| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Some(fetch.clone())
+        });
+    assert!(!db.sink_healthy());
+
+    assert_eq!(db.basic_ref(address).unwrap(), Some(AccountInfo::default()));
+    assert_eq!(
+        db.storage_ref(address, U256::from(1)).unwrap(),
+        U256::from(2)
+    );
+    assert_eq!(db.block_hash_ref(1).unwrap(), hash);
+    assert_eq!(db.block_hash_ref(1_000).unwrap(), B256::ZERO);
+    db.code_by_hash_ref(B256::ZERO).unwrap();
+    assert_eq!(emitted.load(Ordering::Relaxed), 0);
+    let stats = db.stats();
+    assert_eq!(
+        (
+            stats.basic_reads,
+            stats.storage_reads,
+            stats.code_reads,
+            stats.block_hash_reads
+        ),
+        (1, 1, 1, 2)
+    );
+}
+
+#[tokio::test]
+async fn finish_ends_the_capture_with_a_summary() {
+    let address = Address::with_last_byte(1);
+    let mock = MockDb::new().with_storage(address, U256::from(1), U256::from(2));
+    let (mut db, receiver) = RecordingDb::new_with_channel(mock);
+    db.storage_ref(address, U256::from(1)).unwrap();
+    db.finish();
+    assert!(!db.sink_healthy());
+
+    // Still served, but no longer recorded.
+    assert_eq!(db.storage_ref(address, U256::from(2)), Ok(U256::ZERO));
+    // The receiver ends although `db` is alive: the sink was dropped.
+    let fetches = drain_receiver(receiver).await;
+    assert_eq!(
+        fetches,
+        [
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(2)
+            },
+            NewFetch::Summary {
+                stats: DbStats {
+                    storage_reads: 1,
+                    ..Default::default()
+                }
+            },
+        ]
+    );
+    assert_eq!(db.stats().storage_reads, 2);
+}
+
+#[test]
+fn mirror_cache_holds_every_inner_read() {
+    let [holder, missing] = [1, 2].map(Address::with_last_byte);
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let code_hash = bytecode.hash_slow();
+    let info = AccountInfo {
+        balance: U256::from(7),
+        ..Default::default()
+    };
+    let mock = MockDb::new()
+        .with_account(holder, info.clone())
+        .with_storage(holder, U256::from(1), U256::from(2))
+        .with_code(bytecode.clone())
+        .with_block_hash(1, B256::repeat_byte(1));
+    // No sink: the mirror alone keeps what was read.
+    let db = RecordingDb::new(mock).with_mirror_cache();
+    db.basic_ref(holder).unwrap();
+    db.basic_ref(missing).unwrap();
+    db.storage_ref(holder, U256::from(1)).unwrap();
+    db.storage_ref(holder, U256::from(2)).unwrap();
+    db.storage_ref(missing, U256::from(3)).unwrap();
+    db.code_by_hash_ref(code_hash).unwrap();
+    db.block_hash_ref(1).unwrap();
+
+    let cache_db = db.into_cache().unwrap();
+    assert_eq!(cache_db.accounts[&holder].info(), Some(info));
+    assert_eq!(
+        cache_db.accounts[&missing].account_state,
+        AccountState::NotExisting
+    );
+    assert_eq!(
+        cache_db.accounts[&holder].storage,
+        [(U256::from(1), U256::from(2)), (U256::from(2), U256::ZERO)]
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(
+        cache_db.accounts[&missing].storage,
+        [(U256::from(3), U256::ZERO)].into_iter().collect()
+    );
+    assert_eq!(cache_db.contracts[&code_hash], bytecode);
+    assert_eq!(cache_db.block_hashes[&U256::from(1)], B256::repeat_byte(1));
+}
+
+#[test]
+fn inner_db_can_be_reconfigured_mid_session() {
+    let address = Address::with_last_byte(1);
+    let mut db = RecordingDb::new_recording(CacheDB::new(EmptyDB::new())).with_read_cache();
+    assert_eq!(db.storage_ref(address, U256::from(1)), Ok(U256::ZERO));
+
+    let inner = db.inner_mut();
+    inner
+        .insert_account_storage(address, U256::from(1), U256::from(5))
+        .unwrap();
+    inner
+        .insert_account_storage(address, U256::from(2), U256::from(6))
+        .unwrap();
+    // The read cache still answers the slot read before.
+    assert_eq!(db.storage_ref(address, U256::from(1)), Ok(U256::ZERO));
+    assert_eq!(db.storage_ref(address, U256::from(2)), Ok(U256::from(6)));
+
+    db.reset();
+    assert_eq!(db.storage_ref(address, U256::from(1)), Ok(U256::from(5)));
+    assert_eq!(
+        db.take_fetches(),
+        [
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::ZERO
+            },
+            NewFetch::Storage {
+                address,
+                index: U256::from(2),
+                value: U256::from(6)
+            },
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(5)
+            },
+        ]
+    );
+}
+
+#[test]
+fn writes_are_recorded_after_the_reads_they_follow() {
+    let [address, untouched] = [1, 2].map(Address::with_last_byte);
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    cache_db.insert_account_info(address, AccountInfo::default());
+    cache_db
+        .insert_account_storage(address, U256::from(1), U256::from(1))
+        .unwrap();
+    let mut db = RecordingDb::new_recording(cache_db).record_writes();
+    db.basic_ref(address).unwrap();
+    db.storage_ref(address, U256::from(1)).unwrap();
+
+    let info = AccountInfo {
+        nonce: 1,
+        ..Default::default()
+    };
+    let account = Account {
+        info: info.clone(),
+        storage: [
+            (
+                U256::from(1),
+                EvmStorageSlot::new_changed(U256::from(1), U256::from(5)),
+            ),
+            // Read but left unchanged, so not written.
+            (U256::from(2), EvmStorageSlot::new(U256::from(3))),
+        ]
+        .into_iter()
+        .collect(),
+        status: AccountStatus::Touched,
+    };
+    db.commit(
+        [(address, account), (untouched, Account::default())]
+            .into_iter()
+            .collect(),
+    );
+    db.storage_ref(address, U256::from(1)).unwrap();
+
+    assert_eq!(db.inner().accounts[&address].info.nonce, 1);
+    assert_eq!(
+        db.take_fetches(),
+        [
+            NewFetch::Basic {
+                address,
+                account_info: AccountInfo::default()
+            },
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(1)
+            },
+            NewFetch::BasicWrite {
+                address,
+                account_info: info
+            },
+            NewFetch::StorageWrite {
+                address,
+                index: U256::from(1),
+                value: U256::from(5)
+            },
+            NewFetch::Storage {
+                address,
+                index: U256::from(1),
+                value: U256::from(5)
+            },
+        ]
+    );
+}
@@ -0,0 +1,328 @@
+use std::collections::HashSet;
+
+use revm::{
+    db::{CacheDB, EmptyDB, State},
+    primitives::{AccountInfo, Address, Bytecode, Bytes, B256, KECCAK_EMPTY, U256},
+    Database, DatabaseRef,
+};
+use revm_proxy_db::{
+    apply_fetches_to_cache_db, cache_db_fingerprint, cache_db_from_fetches,
+    cache_db_from_fetches_with_policy, divergence_report, fetches_to_bundle_state, verify_replay,
+    FetchKey, MockDb, NewFetch, OrphanStoragePolicy, ProxyDbError, RecordingDb, ReplayDb,
+    ReplayMismatch,
+};
+
+fn storage(address: Address, index: u64, value: u64) -> NewFetch {
+    NewFetch::Storage {
+        address,
+        index: U256::from(index),
+        value: U256::from(value),
+    }
+}
+
+#[test]
+fn storage_before_its_account_is_kept() {
+    let (fetched, storage_only) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let account_info = AccountInfo {
+        balance: U256::from(10),
+        ..Default::default()
+    };
+    let fetches = vec![
+        storage(fetched, 1, 11),
+        storage(storage_only, 1, 21),
+        storage(fetched, 2, 12),
+        NewFetch::Basic {
+            address: fetched,
+            account_info: account_info.clone(),
+        },
+        storage(fetched, 3, 13),
+    ];
+
+    let cache_db = cache_db_from_fetches(fetches.clone()).unwrap();
+    let account = &cache_db.accounts[&fetched];
+    assert_eq!(account.info, account_info);
+    assert_eq!(account.storage.len(), 3);
+    assert_eq!(account.storage[&U256::from(2)], U256::from(12));
+    assert_eq!(
+        cache_db.accounts[&storage_only].storage[&U256::from(1)],
+        U256::from(21)
+    );
+
+    let reversed = cache_db_from_fetches(fetches.into_iter().rev()).unwrap();
+    assert_eq!(
+        cache_db_fingerprint(&reversed),
+        cache_db_fingerprint(&cache_db)
+    );
+}
+
+#[test]
+fn replay_db_fails_on_slots_it_did_not_capture() {
+    let address = Address::with_last_byte(1);
+    let db = ReplayDb::from_fetches([
+        NewFetch::Basic {
+            address,
+            account_info: AccountInfo::default(),
+        },
+        storage(address, 1, 11),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        db.storage_ref(address, U256::from(1)).unwrap(),
+        U256::from(11)
+    );
+    let missing = FetchKey::Storage(address, U256::from(2));
+    let result = db.storage_ref(address, U256::from(2));
+    assert!(matches!(result, Err(ProxyDbError::NotCaptured(key)) if key == missing));
+}
+
+#[test]
+fn rejected_orphan_storage_reports_the_lowest_slot() {
+    let (fetched, first, second) = (
+        Address::with_last_byte(1),
+        Address::with_last_byte(2),
+        Address::with_last_byte(3),
+    );
+    let fetches = vec![
+        storage(second, 1, 31),
+        storage(fetched, 1, 11),
+        storage(first, 7, 27),
+        storage(first, 4, 24),
+        NewFetch::BasicMissing { address: fetched },
+    ];
+
+    for fetches in [fetches.clone(), fetches.into_iter().rev().collect()] {
+        let result = cache_db_from_fetches_with_policy(fetches, OrphanStoragePolicy::Reject);
+        assert!(matches!(
+            result,
+            Err(ProxyDbError::OrphanStorage { address, index })
+                if address == first && index == U256::from(4)
+        ));
+    }
+}
+
+#[test]
+fn mislabeled_code_is_rejected() {
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let code_hash = B256::repeat_byte(0xc0);
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    let result = apply_fetches_to_cache_db(
+        &mut cache_db,
+        [NewFetch::CodeByHash {
+            code_hash,
+            bytecode: bytecode.clone(),
+        }],
+    );
+    assert!(matches!(
+        result,
+        Err(ProxyDbError::CorruptCache { code_hash: hash, actual })
+            if hash == code_hash && actual == bytecode.hash_slow()
+    ));
+    assert!(!cache_db.contracts.contains_key(&code_hash));
+
+    apply_fetches_to_cache_db(
+        &mut cache_db,
+        [NewFetch::CodeByHash {
+            code_hash: KECCAK_EMPTY,
+            bytecode: Bytecode::new(),
+        }],
+    )
+    .unwrap();
+}
+
+#[test]
+fn inline_code_of_a_basic_fetch_fills_contracts() {
+    let address = Address::with_last_byte(1);
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let cache_db = cache_db_from_fetches([NewFetch::Basic {
+        address,
+        account_info: AccountInfo::from_bytecode(bytecode.clone()),
+    }])
+    .unwrap();
+    assert_eq!(cache_db.contracts[&bytecode.hash_slow()], bytecode);
+    assert_eq!(
+        cache_db.accounts[&address].info.code_hash,
+        bytecode.hash_slow()
+    );
+}
+
+#[test]
+fn mismatched_inline_code_is_rejected() {
+    let address = Address::with_last_byte(1);
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let code_hash = B256::repeat_byte(0xc0);
+    let mut cache_db = CacheDB::new(EmptyDB::new());
+    let result = apply_fetches_to_cache_db(
+        &mut cache_db,
+        [NewFetch::Basic {
+            address,
+            account_info: AccountInfo {
+                code_hash,
+                ..AccountInfo::from_bytecode(bytecode.clone())
+            },
+        }],
+    );
+    assert!(matches!(
+        result,
+        Err(ProxyDbError::CorruptCache { code_hash: hash, actual })
+            if hash == code_hash && actual == bytecode.hash_slow()
+    ));
+    assert!(cache_db.accounts.is_empty());
+}
+
+#[test]
+fn empty_inline_code_adds_no_contract() {
+    let (plain, placeholder) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let code_hash = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00])).hash_slow();
+    let fetches = [
+        NewFetch::Basic {
+            address: plain,
+            account_info: AccountInfo {
+                code: Some(Bytecode::new()),
+                ..Default::default()
+            },
+        },
+        // Some backends inline empty code for contracts they did not load the code of.
+        NewFetch::Basic {
+            address: placeholder,
+            account_info: AccountInfo {
+                code_hash,
+                code: Some(Bytecode::new()),
+                ..Default::default()
+            },
+        },
+    ];
+
+    let cache_db = cache_db_from_fetches(fetches).unwrap();
+    assert_eq!(cache_db.accounts[&plain].info.code_hash, KECCAK_EMPTY);
+    assert_eq!(cache_db.accounts[&placeholder].info.code_hash, code_hash);
+    assert_eq!(
+        cache_db.contracts.len(),
+        CacheDB::new(EmptyDB::new()).contracts.len()
+    );
+}
+
+#[test]
+fn bundle_state_seeds_a_revm_state() {
+    let (contract, missing) = (Address::with_last_byte(1), Address::with_last_byte(2));
+    let bytecode = Bytecode::new_raw(Bytes::from_static(&[0x60, 0x01, 0x00]));
+    let account_info = AccountInfo {
+        balance: U256::from(10),
+        code: None,
+        ..AccountInfo::from_bytecode(bytecode.clone())
+    };
+    let bundle = fetches_to_bundle_state([
+        storage(contract, 1, 11),
+        NewFetch::Basic {
+            address: contract,
+            account_info: account_info.clone(),
+        },
+        NewFetch::BasicMissing { address: missing },
+        NewFetch::CodeByHash {
+            code_hash: bytecode.hash_slow(),
+            bytecode: bytecode.clone(),
+        },
+    ])
+    .unwrap();
+
+    let mut state = State::builder()
+        .with_database(EmptyDB::new())
+        .with_bundle_prestate(bundle)
+        .build();
+    let loaded = state.basic(contract).unwrap().unwrap();
+    assert_eq!(loaded.balance, account_info.balance);
+    assert_eq!(loaded.code_hash, bytecode.hash_slow());
+    assert_eq!(
+        state.storage(contract, U256::from(1)).unwrap(),
+        U256::from(11)
+    );
+    assert_eq!(state.code_by_hash(bytecode.hash_slow()).unwrap(), bytecode);
+    assert_eq!(state.basic(missing).unwrap(), None);
+}
+
+#[test]
+fn verify_replay_reports_a_corrupted_slot() {
+    let address = Address::with_last_byte(1);
+    let live = MockDb::new()
+        .with_account(address, AccountInfo::default())
+        .with_storage(address, U256::from(1), U256::from(11))
+        .with_storage(address, U256::from(2), U256::from(12));
+    let db = RecordingDb::new_recording(live);
+    db.basic_ref(address).unwrap();
+    db.storage_ref(address, U256::from(1)).unwrap();
+    db.storage_ref(address, U256::from(2)).unwrap();
+    let fetches = db.take_fetches();
+    let mut replayed = cache_db_from_fetches(fetches.clone()).unwrap();
+    assert_eq!(verify_replay(&fetches, &replayed, db.inner()), Ok(vec![]));
+
+    replayed
+        .insert_account_storage(address, U256::from(2), U256::from(99))
+        .unwrap();
+    assert_eq!(
+        verify_replay(&fetches, &replayed, db.inner()),
+        Ok(vec![ReplayMismatch::Storage {
+            address,
+            index: U256::from(2),
+            replayed: U256::from(99),
+            live: U256::from(12),
+        }])
+    );
+}
+
+#[test]
+fn divergence_report_lists_one_sided_keys_and_differing_values() {
+    let [holder, stray, unread] = [1, 2, 3].map(Address::with_last_byte);
+    let live = MockDb::new()
+        .with_account(holder, AccountInfo::default())
+        .with_storage(holder, U256::from(1), U256::from(11))
+        .with_storage(holder, U256::from(2), U256::from(12));
+    let db = RecordingDb::new_recording(live);
+    db.basic_ref(holder).unwrap();
+    db.storage_ref(holder, U256::from(1)).unwrap();
+    db.storage_ref(holder, U256::from(2)).unwrap();
+    let mut replayed = cache_db_from_fetches(db.take_fetches()).unwrap();
+    let access_list = [
+        (holder, vec![U256::from(1), U256::from(2), U256::from(3)]),
+        (unread, vec![]),
+    ];
+    let report = divergence_report(db.inner(), &replayed, &access_list).unwrap();
+    assert_eq!(
+        report.missing_from_replay,
+        [
+            FetchKey::Storage(holder, U256::from(3)),
+            FetchKey::Basic(unread)
+        ]
+    );
+    assert!(report.mismatches.is_empty() && report.unexpected_in_replay.is_empty());
+
+    replayed
+        .insert_account_storage(holder, U256::from(2), U256::from(99))
+        .unwrap();
+    replayed
+        .insert_account_storage(holder, U256::from(9), U256::from(1))
+        .unwrap();
+    replayed.insert_account_info(stray, AccountInfo::default());
+    let report = divergence_report(db.inner(), &replayed, &access_list).unwrap();
+    assert_eq!(
+        report.mismatches,
+        [ReplayMismatch::Storage {
+            address: holder,
+            index: U256::from(2),
+            replayed: U256::from(99),
+            live: U256::from(12),
+        }]
+    );
+    assert_eq!(report.missing_from_replay.len(), 2);
+    assert_eq!(
+        report
+            .unexpected_in_replay
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>(),
+        HashSet::from([
+            FetchKey::Storage(holder, U256::from(9)),
+            FetchKey::Basic(stray)
+        ])
+    );
+    assert!(!report.is_empty());
+}
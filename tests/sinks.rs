@@ -0,0 +1,214 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use revm::{db::EmptyDB, primitives::Address, DatabaseRef};
+use revm_proxy_db::{
+    drain_receiver, BackpressurePolicy, BoundedSender, FetchSink, MockDb, NewFetch, RecordingDb,
+};
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver};
+
+fn read_accounts(db: &impl DatabaseRef, count: u8) {
+    for byte in 0..count {
+        let _ = db.basic_ref(Address::with_last_byte(byte));
+    }
+}
+
+fn drain_bounded(receiver: &mut Receiver<NewFetch>) -> Vec<NewFetch> {
+    let mut fetches = Vec::new();
+    while let Ok(fetch) = receiver.try_recv() {
+        fetches.push(fetch);
+    }
+    fetches
+}
+
+fn bounded(policy: BackpressurePolicy) -> (RecordingDb<MockDb, BoundedSender>, Receiver<NewFetch>) {
+    let (sender, receiver) = channel(2);
+    let db = RecordingDb::new(MockDb::new()).with_bounded_sender(sender, policy);
+    (db, receiver)
+}
+
+#[test]
+fn full_bounded_channel_drops_fetches() {
+    let (db, mut receiver) = bounded(BackpressurePolicy::Drop);
+    read_accounts(&db, 5);
+
+    assert_eq!(drain_bounded(&mut receiver).len(), 2);
+    assert!(!db.overflowed());
+}
+
+#[test]
+fn full_bounded_channel_flags_overflow() {
+    let (db, mut receiver) = bounded(BackpressurePolicy::Error);
+    read_accounts(&db, 2);
+    assert!(!db.overflowed());
+    read_accounts(&db, 5);
+
+    assert_eq!(drain_bounded(&mut receiver).len(), 2);
+    assert!(db.overflowed());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn blocking_on_a_current_thread_runtime_flags_overflow() {
+    let (db, mut receiver) = bounded(BackpressurePolicy::Block);
+    read_accounts(&db, 5);
+
+    assert_eq!(drain_bounded(&mut receiver).len(), 2);
+    assert!(db.overflowed());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn blocking_waits_for_the_consumer() {
+    let (db, mut receiver) = bounded(BackpressurePolicy::Block);
+    let consumer = tokio::spawn(async move {
+        let mut fetches = Vec::new();
+        while let Some(fetch) = receiver.recv().await {
+            fetches.push(fetch);
+        }
+        fetches
+    });
+    read_accounts(&db, 5);
+    drop(db);
+
+    assert_eq!(consumer.await.unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn fan_out_reaches_every_receiver() {
+    let (db, first) = RecordingDb::new_with_channel(MockDb::new());
+    let (sender, second) = unbounded_channel();
+    let (third_sender, third) = unbounded_channel();
+    let db = db.add_sender(sender).add_sender(third_sender);
+    read_accounts(&db, 3);
+
+    drop(third);
+    assert_eq!(db.sink().unwrap().len(), 3);
+    read_accounts(&db, 1);
+    assert_eq!(db.sink().unwrap().len(), 2);
+    drop(db);
+
+    let fetches = drain_receiver(first).await;
+    assert_eq!(fetches.len(), 4);
+    assert_eq!(drain_receiver(second).await, fetches);
+}
+
+#[tokio::test]
+async fn flushed_fetches_are_all_drained() {
+    let (mut db, receiver) = RecordingDb::new_with_channel(MockDb::new());
+    read_accounts(&db, 10);
+    db.flush();
+    read_accounts(&db, 10);
+
+    assert_eq!(drain_receiver(receiver).await.len(), 10);
+}
+
+#[test]
+fn fetches_arrive_in_batches() {
+    let (sender, mut receiver) = unbounded_channel();
+    let db = RecordingDb::new(MockDb::new()).with_batch_size(sender, 3);
+    read_accounts(&db, 7);
+
+    let mut batches = Vec::new();
+    while let Ok(batch) = receiver.try_recv() {
+        batches.push(batch.len());
+    }
+    assert_eq!(batches, [3, 3]);
+    db.flush_batch();
+    assert_eq!(receiver.try_recv().unwrap().len(), 1);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn sequence_numbers_are_gap_free_across_clones() {
+    let (sender, mut receiver) = unbounded_channel();
+    let db = RecordingDb::new(EmptyDB::new()).with_sequence_numbers(sender);
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            let db = db.clone();
+            scope.spawn(move || read_accounts(&db, 25));
+        }
+    });
+
+    let mut seqs = Vec::new();
+    while let Ok(fetch) = receiver.try_recv() {
+        seqs.push(fetch.seq);
+    }
+    assert_eq!(seqs, (0..100).collect::<Vec<_>>());
+    assert_eq!(db.sink().unwrap().next_seq(), 100);
+}
+
+#[test]
+fn batch_is_flushed_when_the_last_clone_drops() {
+    let (sender, mut receiver) = unbounded_channel();
+    let db = RecordingDb::new(EmptyDB::new()).with_batch_size(sender, 10);
+    let clone = db.clone();
+    read_accounts(&db, 2);
+    read_accounts(&clone, 2);
+
+    drop(db);
+    assert!(receiver.try_recv().is_err());
+    drop(clone);
+    assert_eq!(receiver.try_recv().unwrap().len(), 4);
+}
+
+#[derive(Default)]
+struct CountingSink(AtomicUsize);
+
+impl FetchSink for CountingSink {
+    fn record(&self, _fetch: NewFetch) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn swapped_dyn_sinks_see_only_their_own_fetches() {
+    let memory = Arc::new(Mutex::new(Vec::new()));
+    let mut db = RecordingDb::new(MockDb::new()).with_dyn_sink(memory.clone());
+    read_accounts(&db, 3);
+
+    let counter = Arc::new(CountingSink::default());
+    assert!(db.set_dyn_sink(counter.clone()).is_some());
+    read_accounts(&db, 2);
+
+    let recorded = memory.lock().unwrap();
+    assert_eq!(recorded.len(), 3);
+    assert_eq!(
+        recorded.last(),
+        Some(&NewFetch::BasicMissing {
+            address: Address::with_last_byte(2)
+        })
+    );
+    assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn with_sender_replaces_the_sink() {
+    let (db, first) = RecordingDb::new_with_channel(MockDb::new());
+    read_accounts(&db, 1);
+    let (sender, second) = unbounded_channel();
+    let db = db.with_sender(sender);
+    read_accounts(&db, 2);
+    drop(db);
+
+    assert_eq!(drain_receiver(first).await.len(), 1);
+    assert_eq!(drain_receiver(second).await.len(), 2);
+}
+
+#[tokio::test]
+async fn any_sink_implementation_records_the_same_fetches() {
+    let (sender, receiver) = unbounded_channel();
+    let db = RecordingDb::new(MockDb::new()).with_sender(sender);
+    read_accounts(&db, 3);
+    drop(db);
+    let sent = drain_receiver(receiver).await;
+
+    let db = RecordingDb::new_recording(MockDb::new());
+    read_accounts(&db, 3);
+    assert_eq!(db.take_fetches(), sent);
+
+    let collected = Arc::new(Mutex::new(Vec::new()));
+    let db = RecordingDb::new(MockDb::new()).with_sink(collected.clone());
+    read_accounts(&db, 3);
+    assert_eq!(*collected.lock().unwrap(), sent);
+}
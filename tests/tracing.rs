@@ -0,0 +1,70 @@
+#![cfg(feature = "tracing")]
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use revm::{
+    primitives::{Address, U256},
+    DatabaseRef,
+};
+use revm_proxy_db::{MockDb, RecordingDb};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+/// Keeps the message of every event of this crate.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<String>>>);
+
+struct Message<'a>(&'a mut Option<String>);
+
+impl Visit for Message<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            *self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == "revm_proxy_db"
+    }
+
+    fn new_span(&self, _span: &span::Attributes<'_>) -> span::Id {
+        span::Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = None;
+        event.record(&mut Message(&mut message));
+        self.0.lock().unwrap().extend(message);
+    }
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+#[test]
+fn lookups_emit_trace_events() {
+    let capture = Capture::default();
+    let db = RecordingDb::new(MockDb::new());
+    tracing::subscriber::with_default(capture.clone(), || {
+        db.basic_ref(Address::ZERO).unwrap();
+        db.storage_ref(Address::ZERO, U256::from(1)).unwrap();
+        db.block_hash_ref(1).unwrap();
+    });
+
+    assert_eq!(
+        *capture.0.lock().unwrap(),
+        ["basic", "storage", "block_hash"]
+    );
+}